@@ -0,0 +1,51 @@
+use std::path::{Path, PathBuf};
+
+use crate::keyframe::Keyframes;
+
+/// Frame range and keyframed camera settings of an animation rendered with
+/// `Camera::render_animation`.
+///
+/// Time is measured in frames: frame n spans the ray times [n, n+1), and all
+/// keyframes, including the camera's `motion`, those of animated `Sphere`,
+/// `Translate` and `RotateY` objects and those of materials, are keyed in frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Animation {
+    pub start_frame: usize,
+    pub end_frame: usize,              // Last frame, inclusive
+    pub vfov: Keyframes<f64>,          // Keyframed vertical field of view, overrides the camera's
+    pub focus_dist: Keyframes<f64>,    // Keyframed focus distance, overrides the camera's
+    pub defocus_angle: Keyframes<f64>, // Keyframed defocus blur angle, overrides the camera's
+    pub extension: String,             // File extension, and so format, of the frame images
+}
+
+impl Default for Animation {
+    fn default() -> Self {
+        Self {
+            start_frame: 1,
+            end_frame: 1,
+            vfov: Keyframes::default(),
+            focus_dist: Keyframes::default(),
+            defocus_angle: Keyframes::default(),
+            extension: "png".to_string(),
+        }
+    }
+}
+
+impl Animation {
+    /// File name of `frame`, e.g. `frame_0001.png`.
+    pub fn file_name(&self, frame: usize) -> String {
+        format!("frame_{:04}.{}", frame, self.extension)
+    }
+
+    /// Checkpoint file of `frame` next to `path`, e.g. `render_0001.ckpt` for
+    /// `render.ckpt`.
+    pub fn checkpoint_path(&self, path: &Path, frame: usize) -> PathBuf {
+        let mut name = path.file_stem().unwrap_or_default().to_os_string();
+        name.push(format!("_{:04}", frame));
+        if let Some(extension) = path.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        path.with_file_name(name)
+    }
+}
@@ -0,0 +1,163 @@
+use nalgebra::Vector3;
+
+use crate::film::Film;
+
+/// Radiance of a path split by the bounce the light was emitted at.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Radiance {
+    pub emission: Vector3<f64>, // Emitted by the first surface, or the background on a miss
+    pub direct: Vector3<f64>,   // Emitted by the surface reached after one bounce
+    pub indirect: Vector3<f64>, // Everything reaching the camera after two or more bounces
+}
+
+impl Radiance {
+    pub fn total(&self) -> Vector3<f64> {
+        self.emission + self.direct + self.indirect
+    }
+
+    /// Adds `light` that was emitted after `bounce` bounces.
+    pub fn add(&mut self, bounce: usize, light: Vector3<f64>) {
+        match bounce {
+            0 => self.emission += light,
+            1 => self.direct += light,
+            _ => self.indirect += light,
+        }
+    }
+}
+
+/// Arbitrary output variables recorded at the first hit of a camera ray.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AovSample {
+    pub hit: bool,
+    pub albedo: Vector3<f64>,
+    pub normal: Vector3<f64>,
+    pub position: Vector3<f64>,
+    pub depth: f64, // Distance from the ray origin to the hit point
+    pub object_id: usize,
+}
+
+/// The output variables that can be extracted from a film.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    Albedo,
+    Normal,
+    Position,
+    Depth,
+    ObjectId,
+    Emission,
+    Direct,
+    Indirect,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 8] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Position,
+        Aov::Depth,
+        Aov::ObjectId,
+        Aov::Emission,
+        Aov::Direct,
+        Aov::Indirect,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Position => "position",
+            Aov::Depth => "depth",
+            Aov::ObjectId => "object_id",
+            Aov::Emission => "emission",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+        }
+    }
+}
+
+/// Per-pixel sums of the output variables, stored next to the beauty pass of a `Film`.
+///
+/// Normal, position and depth are averaged over the samples that hit something,
+/// albedo and the radiance splits over all samples of the pixel.
+#[derive(Debug, Clone)]
+pub struct AovBuffers {
+    pub albedo: Vec<Vector3<f64>>,
+    pub normal: Vec<Vector3<f64>>,
+    pub position: Vec<Vector3<f64>>,
+    pub depth: Vec<f64>,
+    pub object_id: Vec<Option<usize>>, // Object hit by the first sample that hit anything
+    pub emission: Vec<Vector3<f64>>,
+    pub direct: Vec<Vector3<f64>>,
+    pub indirect: Vec<Vector3<f64>>,
+    pub hits: Vec<usize>, // Samples that hit any object
+}
+
+impl AovBuffers {
+    pub fn new(len: usize) -> Self {
+        Self {
+            albedo: vec![Vector3::zeros(); len],
+            normal: vec![Vector3::zeros(); len],
+            position: vec![Vector3::zeros(); len],
+            depth: vec![0.0; len],
+            object_id: vec![None; len],
+            emission: vec![Vector3::zeros(); len],
+            direct: vec![Vector3::zeros(); len],
+            indirect: vec![Vector3::zeros(); len],
+            hits: vec![0; len],
+        }
+    }
+
+    pub fn add_sample(&mut self, idx: usize, aov: &AovSample, radiance: &Radiance) {
+        self.albedo[idx] += aov.albedo;
+        self.emission[idx] += radiance.emission;
+        self.direct[idx] += radiance.direct;
+        self.indirect[idx] += radiance.indirect;
+        if aov.hit {
+            self.normal[idx] += aov.normal;
+            self.position[idx] += aov.position;
+            self.depth[idx] += aov.depth;
+            self.object_id[idx] = self.object_id[idx].or(Some(aov.object_id));
+            self.hits[idx] += 1;
+        }
+    }
+
+    /// Adds pixel `src` of `other` to pixel `dst`.
+    pub fn merge_pixel(&mut self, dst: usize, other: &AovBuffers, src: usize) {
+        self.albedo[dst] += other.albedo[src];
+        self.normal[dst] += other.normal[src];
+        self.position[dst] += other.position[src];
+        self.depth[dst] += other.depth[src];
+        self.object_id[dst] = self.object_id[dst].or(other.object_id[src]);
+        self.emission[dst] += other.emission[src];
+        self.direct[dst] += other.direct[src];
+        self.indirect[dst] += other.indirect[src];
+        self.hits[dst] += other.hits[src];
+    }
+
+    /// Builds a film holding a single output variable, `samples` being the
+    /// per-pixel sample counts of the beauty pass.
+    ///
+    /// Object IDs are stored unnormalized in all three channels.
+    pub fn film(&self, aov: Aov, width: usize, height: usize, samples: &[usize]) -> Film {
+        let mut film = Film::new(width, height);
+        for (idx, &pixel_samples) in samples.iter().enumerate() {
+            let (color, count) = match aov {
+                Aov::Albedo => (self.albedo[idx], pixel_samples),
+                Aov::Normal => (self.normal[idx], self.hits[idx]),
+                Aov::Position => (self.position[idx], self.hits[idx]),
+                Aov::Depth => (Vector3::repeat(self.depth[idx]), self.hits[idx]),
+                Aov::ObjectId => match self.object_id[idx] {
+                    Some(id) => (Vector3::repeat(id as f64), 1),
+                    None => (Vector3::zeros(), 0),
+                },
+                Aov::Emission => (self.emission[idx], pixel_samples),
+                Aov::Direct => (self.direct[idx], pixel_samples),
+                Aov::Indirect => (self.indirect[idx], pixel_samples),
+            };
+            film.color[idx] = color;
+            film.weight[idx] = count as f64;
+            film.samples[idx] = count;
+        }
+        film
+    }
+}
@@ -0,0 +1,152 @@
+use std::{f64::consts::PI, path::Path, sync::Arc};
+
+use image::{
+    error::{ParameterError, ParameterErrorKind},
+    GrayImage, ImageError, ImageResult,
+};
+use nalgebra::Vector2;
+
+use crate::util::concentric_disk;
+
+/// Outline of the lens opening, which gives out of focus highlights their shape.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum ApertureShape {
+    /// Circular opening.
+    #[default]
+    Disk,
+    /// Regular polygon of `blades` sides inscribed into the unit circle, rotated
+    /// by `rotation` degrees.
+    Polygon { blades: usize, rotation: f64 },
+    /// Arbitrary opening given by a grayscale transmission mask.
+    Mask(Arc<ApertureMask>),
+}
+
+/// Lens aperture of the thin lens camera, its radius is given by the camera's
+/// `defocus_angle`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Aperture {
+    pub shape: ApertureShape,
+    pub cat_eye: f64, // Strength of the optical vignetting towards the image corners, 0 disables
+}
+
+/// Grayscale image stretched over the square enclosing the unit circle, lens
+/// positions are sampled proportionally to its brightness.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    row_cdf: Vec<f64>,   // Cumulative brightness of the rows, normalized
+    pixel_cdf: Vec<f64>, // Cumulative brightness of the pixels within each row, normalized
+}
+
+/// Index of the interval of the normalized `cdf` containing `u`, and `u` remapped
+/// to [0,1) within that interval.
+fn sample_cdf(cdf: &[f64], u: f64) -> (usize, f64) {
+    let idx = cdf.partition_point(|&c| c <= u).min(cdf.len() - 1);
+    let lower = if idx == 0 { 0.0 } else { cdf[idx - 1] };
+    let width = cdf[idx] - lower;
+    let remapped = if width > 0.0 {
+        (u - lower) / width
+    } else {
+        0.5
+    };
+    (idx, remapped.clamp(0.0, 1.0))
+}
+
+/// Normalizes `values` into a cumulative distribution, returning their total.
+fn build_cdf(values: &[f64], cdf: &mut [f64]) -> f64 {
+    let mut sum = 0.0;
+    for (c, v) in cdf.iter_mut().zip(values) {
+        sum += v;
+        *c = sum;
+    }
+    if sum > 0.0 {
+        cdf.iter_mut().for_each(|c| *c /= sum);
+    } else {
+        // An all black row is never picked, but keep its distribution valid.
+        let n = cdf.len() as f64;
+        cdf.iter_mut()
+            .enumerate()
+            .for_each(|(i, c)| *c = (i + 1) as f64 / n);
+    }
+    sum
+}
+
+impl ApertureMask {
+    /// Fails for empty and all black images, which let no light through.
+    pub fn new(image: &GrayImage) -> ImageResult<Self> {
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let mut pixel_cdf = vec![0.0; width * height];
+        let mut row_sums = vec![0.0; height];
+        for (y, row_sum) in row_sums.iter_mut().enumerate() {
+            let row: Vec<f64> = (0..width)
+                .map(|x| image.get_pixel(x as u32, y as u32).0[0] as f64 / 255.0)
+                .collect();
+            *row_sum = build_cdf(&row, &mut pixel_cdf[y * width..(y + 1) * width]);
+        }
+        let mut row_cdf = vec![0.0; height];
+        if build_cdf(&row_sums, &mut row_cdf) <= 0.0 {
+            return Err(ImageError::Parameter(ParameterError::from_kind(
+                ParameterErrorKind::Generic("aperture mask lets no light through".into()),
+            )));
+        }
+        Ok(Self {
+            width,
+            height,
+            row_cdf,
+            pixel_cdf,
+        })
+    }
+
+    /// Loads the mask from an image file, colors are converted to their luma.
+    pub fn open<P: AsRef<Path>>(path: P) -> ImageResult<Self> {
+        Self::new(&image::open(path)?.to_luma8())
+    }
+
+    /// Maps the uniform sample `u` to a point in [-1,1]², y pointing up.
+    pub fn sample(&self, u: &Vector2<f64>) -> Vector2<f64> {
+        let (row, v) = sample_cdf(&self.row_cdf, u.y);
+        let (col, u) = sample_cdf(
+            &self.pixel_cdf[row * self.width..(row + 1) * self.width],
+            u.x,
+        );
+        Vector2::new(
+            2.0 * (col as f64 + u) / self.width as f64 - 1.0,
+            1.0 - 2.0 * (row as f64 + v) / self.height as f64,
+        )
+    }
+}
+
+impl ApertureShape {
+    /// Maps the uniform sample `u` to a point on the opening, within the unit circle
+    /// or, for masks, the square enclosing it.
+    pub fn sample(&self, u: &Vector2<f64>) -> Vector2<f64> {
+        match self {
+            ApertureShape::Disk => concentric_disk(u),
+            ApertureShape::Polygon { blades, rotation } => {
+                // Pick one of the triangles fanning out from the center, then a
+                // uniform point inside it.
+                let blades = (*blades).max(3);
+                let scaled = u.x * blades as f64;
+                let blade = (scaled as usize).min(blades - 1);
+                let u0 = scaled - blade as f64;
+                let angle = |k: usize| rotation.to_radians() + 2.0 * PI * k as f64 / blades as f64;
+                let (a0, a1) = (angle(blade), angle(blade + 1));
+                let v0 = Vector2::new(a0.cos(), a0.sin());
+                let v1 = Vector2::new(a1.cos(), a1.sin());
+                let su = u.y.sqrt();
+                su * ((1.0 - u0) * v0 + u0 * v1)
+            }
+            ApertureShape::Mask(mask) => mask.sample(u),
+        }
+    }
+}
+
+impl Aperture {
+    /// Whether the lens point `p` is blocked by the lens barrel for the image
+    /// position `image_pos`, which is 0 at the image center and has a length of 1
+    /// in the corners. Produces the cat-eye shaped bokeh of real lenses.
+    pub fn vignetted(&self, p: &Vector2<f64>, image_pos: &Vector2<f64>) -> bool {
+        self.cat_eye > 0.0 && (p - self.cat_eye * image_pos).norm_squared() > 1.0
+    }
+}
@@ -0,0 +1,552 @@
+use std::f64::consts::PI;
+
+use nalgebra::Vector3;
+
+use crate::{
+    aov::{AovSample, Radiance},
+    camera::{Camera, CameraImportance},
+    film::LightSplat,
+    hit::{HitRecord, Hittable},
+    integrator::{record_aov, MisHeuristic},
+    onb::Onb,
+    ray::Ray,
+    sampler::get_2d,
+    util::{cosine_direction, Interval},
+};
+
+/// Where a subpath vertex lies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+}
+
+/// Vertex of a camera or light subpath. Densities are measured per unit area at
+/// the vertex, so strategies sampling it from either end can be compared.
+#[derive(Debug, Clone)]
+struct Vertex<'a> {
+    kind: VertexKind,
+    p: Vector3<f64>,
+    normal: Vector3<f64>, // Surface normal facing the incoming ray, zero for the camera
+    rec: Option<HitRecord<'a>>, // Hit the vertex was created from, None for the camera
+    ray_in: Option<Ray>,  // Ray that reached a surface vertex
+    beta: Vector3<f64>,   // Throughput of the subpath up to this vertex
+    delta: bool,          // Specular, cannot be connected to
+    pdf_fwd: f64,         // Density of sampling the vertex from its own subpath
+    pdf_rev: f64,         // Density of sampling the vertex from the other end
+}
+
+impl<'a> Vertex<'a> {
+    fn camera(p: Vector3<f64>, delta: bool) -> Self {
+        Self {
+            kind: VertexKind::Camera,
+            p,
+            normal: Vector3::zeros(),
+            rec: None,
+            ray_in: None,
+            beta: Vector3::repeat(1.0),
+            delta,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }
+    }
+
+    fn light(rec: HitRecord<'a>, beta: Vector3<f64>, pdf_fwd: f64) -> Self {
+        Self {
+            kind: VertexKind::Light,
+            p: rec.p,
+            normal: rec.normal,
+            rec: Some(rec),
+            ray_in: None,
+            beta,
+            delta: false,
+            pdf_fwd,
+            pdf_rev: 0.0,
+        }
+    }
+
+    /// Material of a surface vertex evaluated for light leaving towards `next`,
+    /// without the cosine at this vertex. Zero for specular materials.
+    fn f(&self, next: &Vertex) -> Vector3<f64> {
+        match (&self.rec, &self.ray_in) {
+            (Some(rec), Some(ray_in)) => rec.material.bsdf(ray_in, rec, &(next.p - self.p)),
+            _ => Vector3::zeros(),
+        }
+    }
+
+    /// Whether light reaching this surface vertex is scattered at all. Emitters end
+    /// the path, and light cannot even be sampled from a point on a sphere light.
+    fn scatters(&self) -> bool {
+        match (&self.rec, &self.ray_in) {
+            (Some(rec), Some(ray_in)) => rec.material.scatter(ray_in, rec).is_some(),
+            _ => false,
+        }
+    }
+
+    /// Light emitted by the vertex towards `next`.
+    fn le(&self, next: &Vertex) -> Vector3<f64> {
+        match &self.rec {
+            Some(rec) if self.normal.dot(&(next.p - self.p)) > 0.0 => {
+                rec.material.emitted(&rec.uv, &rec.p, rec)
+            }
+            _ => Vector3::zeros(),
+        }
+    }
+
+    /// Converts the solid angle density `pdf` of sampling `next` from this vertex to
+    /// a density per unit area at `next`.
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        let direction = next.p - self.p;
+        let distance_squared = direction.norm_squared();
+        if distance_squared == 0.0 {
+            return 0.0;
+        }
+        let cos = match next.kind {
+            VertexKind::Camera => 1.0,
+            _ => next.normal.dot(&direction).abs() / distance_squared.sqrt(),
+        };
+        pdf * cos / distance_squared
+    }
+
+    /// Density of sampling `next` when continuing the subpath from this vertex.
+    fn pdf(&self, camera: Option<&CameraImportance>, next: &Vertex) -> f64 {
+        let direction = next.p - self.p;
+        let pdf = match self.kind {
+            VertexKind::Camera => camera.map_or(0.0, |camera| camera.pdf_dir(&direction)),
+            VertexKind::Light => return self.pdf_light(next),
+            VertexKind::Surface => {
+                let (Some(rec), Some(ray_in)) = (&self.rec, &self.ray_in) else {
+                    return 0.0;
+                };
+                match rec.material.scatter(ray_in, rec) {
+                    Some(srec) if !srec.skip_pdf => srec.pdf.value(&direction),
+                    _ => 0.0,
+                }
+            }
+        };
+        self.convert_density(pdf, next)
+    }
+
+    /// Density of an emitter at this vertex sending its light to `next`.
+    fn pdf_light(&self, next: &Vertex) -> f64 {
+        let cos = self.normal.dot(&(next.p - self.p).normalize());
+        if cos <= 0.0 {
+            return 0.0;
+        }
+        self.convert_density(cos / PI, next)
+    }
+}
+
+/// Area density of `lights.random(origin)` followed by a hit on `lights` choosing
+/// `p` as an emission point. Zero where another light hides `p` from `origin` or
+/// `origin` sees its back face, only the camera paths find such points.
+fn emission_pdf(lights: &Hittable, origin: &Vector3<f64>, p: &Vector3<f64>) -> f64 {
+    let direction = p - origin;
+    match lights.hit(
+        &Ray::new(*origin, direction),
+        &Interval::new(0.001, f64::INFINITY),
+    ) {
+        Some(rec) if rec.front_face && (rec.t - 1.0).abs() < 1e-4 => {
+            let distance_squared = direction.norm_squared();
+            let cos = rec.normal.dot(&direction).abs() / distance_squared.sqrt();
+            lights.pdf_value(origin, &direction) * cos / distance_squared
+        }
+        _ => 0.0,
+    }
+}
+
+fn visible(world: &Hittable, a: &Vector3<f64>, b: &Vector3<f64>, time: f64) -> bool {
+    let direction = b - a;
+    let distance = direction.norm();
+    let ray = Ray::new_with_time(*a, direction / distance, time);
+    world
+        .hit(&ray, &Interval::new(0.001, distance - 0.001))
+        .is_none()
+}
+
+/// Extends `path` by following `ray` through `world` until it holds `max_vertices`
+/// vertices. `pdf` is the solid angle density `ray` was sampled with. Returns the
+/// throughput of the ray leaving the scene, if it does.
+fn random_walk<'a>(
+    world: &'a Hittable,
+    mut ray: Ray,
+    mut beta: Vector3<f64>,
+    mut pdf: f64,
+    max_vertices: usize,
+    path: &mut Vec<Vertex<'a>>,
+) -> Option<Vector3<f64>> {
+    while path.len() < max_vertices {
+        let Some(rec) = world.hit(&ray, &Interval::new(0.001, f64::INFINITY)) else {
+            return Some(beta);
+        };
+        let mat = rec.material;
+        let mut vertex = Vertex {
+            kind: VertexKind::Surface,
+            p: rec.p,
+            normal: rec.normal,
+            rec: Some(rec.clone()),
+            ray_in: Some(ray.clone()),
+            beta,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        };
+        let prev = path.last().expect("subpaths start at an endpoint");
+        vertex.pdf_fwd = prev.convert_density(pdf, &vertex);
+        if path.len() + 1 == max_vertices {
+            path.push(vertex);
+            break;
+        }
+
+        let Some(srec) = mat.scatter(&ray, &rec) else {
+            path.push(vertex);
+            break;
+        };
+        let pdf_rev;
+        let scattered = if srec.skip_pdf {
+            // Specular bounces cannot be sampled from the other end.
+            vertex.delta = true;
+            beta = beta.component_mul(&srec.attenuation);
+            (pdf, pdf_rev) = (0.0, 0.0);
+            srec.skip_pdf_ray
+        } else {
+            let direction = srec.pdf.generate();
+            pdf = srec.pdf.value(&direction);
+            if pdf <= 0.0 {
+                path.push(vertex);
+                break;
+            }
+            let scattered = Ray::new_with_time(rec.p, direction, ray.time);
+            let scattering_pdf = mat.scattering_pdf(&ray, &scattered, &rec);
+            beta = beta.component_mul(&(srec.attenuation * scattering_pdf / pdf));
+            pdf_rev = srec.pdf.value(&-ray.direction);
+            scattered
+        };
+        let n = path.len();
+        path[n - 1].pdf_rev = vertex.convert_density(pdf_rev, &path[n - 1]);
+        path.push(vertex);
+        if beta == Vector3::zeros() {
+            break;
+        }
+        ray = scattered;
+    }
+    None
+}
+
+/// Picks an emission point with `lights.random(origin)`, the first hit on `lights`
+/// in the sampled direction. Returns the hit, the emitted radiance and the area
+/// density of the point, or None if nothing is emitted or the back face was hit.
+fn sample_emission<'a>(
+    lights: &'a Hittable,
+    origin: &Vector3<f64>,
+    time: f64,
+) -> Option<(HitRecord<'a>, Vector3<f64>, f64)> {
+    let direction = lights.random(origin);
+    let rec = lights.hit(
+        &Ray::new_with_time(*origin, direction, time),
+        &Interval::new(0.001, f64::INFINITY),
+    )?;
+    let pdf_pos = emission_pdf(lights, origin, &rec.p);
+    let le = rec.material.emitted(&rec.uv, &rec.p, &rec);
+    if pdf_pos <= 0.0 || le == Vector3::zeros() {
+        return None;
+    }
+    Some((rec, le, pdf_pos))
+}
+
+/// Starts a light path at a point picked by `sample_emission` and follows it for up
+/// to `max_vertices` vertices.
+fn light_subpath<'a>(
+    world: &'a Hittable,
+    lights: &'a Hittable,
+    origin: &Vector3<f64>,
+    time: f64,
+    max_vertices: usize,
+) -> Vec<Vertex<'a>> {
+    let mut path = vec![];
+    if max_vertices == 0 {
+        return path;
+    }
+    let Some((rec, le, pdf_pos)) = sample_emission(lights, origin, time) else {
+        return path;
+    };
+
+    let direction = Onb::new_from_w(rec.normal).local_v(cosine_direction(&get_2d()));
+    let pdf_dir = rec.normal.dot(&direction.normalize()) / PI;
+    let ray = Ray::new_with_time(rec.p, direction, time);
+    path.push(Vertex::light(rec, le, pdf_pos));
+    if pdf_dir > 0.0 {
+        // Cosine weighted emission cancels the cosine of the emitted radiance.
+        random_walk(
+            world,
+            ray,
+            le * PI / pdf_pos,
+            pdf_dir,
+            max_vertices,
+            &mut path,
+        );
+    }
+    path
+}
+
+/// Connection of the first `s` light subpath vertices to the first `t` camera
+/// subpath vertices.
+struct Strategy<'p, 'a> {
+    light_path: &'p [Vertex<'a>],
+    camera_path: &'p [Vertex<'a>],
+    s: usize,
+    t: usize,
+    sampled: Option<&'p Vertex<'a>>, // Endpoint replacing the last vertex when s or t is 1
+}
+
+impl Strategy<'_, '_> {
+    fn qs(&self) -> Option<&Vertex<'_>> {
+        match self.s {
+            0 => None,
+            1 => self.sampled,
+            s => Some(&self.light_path[s - 1]),
+        }
+    }
+
+    fn pt(&self) -> &Vertex<'_> {
+        match self.t {
+            1 => self.sampled.expect("t=1 connects to a sampled lens point"),
+            t => &self.camera_path[t - 1],
+        }
+    }
+
+    /// Multiple importance sampling weight of the strategy among all others that
+    /// could have produced the same path. Densities at the two connected vertices
+    /// and their predecessors are recomputed for this connection.
+    fn mis_weight(
+        &self,
+        camera: Option<&CameraImportance>,
+        lights: &Hittable,
+        origin: &Vector3<f64>,
+        heuristic: MisHeuristic,
+    ) -> f64 {
+        let (s, t) = (self.s, self.t);
+        if s + t == 2 {
+            return 1.0;
+        }
+        let (qs, pt) = (self.qs(), self.pt());
+        let qs_minus = (s > 1).then(|| &self.light_path[s - 2]);
+        let pt_minus = (t > 1).then(|| &self.camera_path[t - 2]);
+
+        // (pdf_fwd, pdf_rev, delta) of the vertices taking part in the connection.
+        let densities = |v: &Vertex| (v.pdf_fwd, v.pdf_rev, v.delta);
+        let subpath = |path: &[Vertex], n: usize| match (n, self.sampled) {
+            (1, Some(sampled)) => vec![densities(sampled)],
+            _ => path[..n].iter().map(densities).collect::<Vec<_>>(),
+        };
+        let mut light = subpath(self.light_path, s);
+        let mut cam = subpath(self.camera_path, t);
+        cam[t - 1].2 = false;
+        cam[t - 1].1 = match qs {
+            Some(qs) => qs.pdf(camera, pt),
+            None => emission_pdf(lights, origin, &pt.p),
+        };
+        if let Some(pt_minus) = pt_minus {
+            cam[t - 2].1 = match qs {
+                Some(_) => pt.pdf(camera, pt_minus),
+                None => pt.pdf_light(pt_minus),
+            };
+        }
+        if let Some(qs) = qs {
+            light[s - 1].2 = false;
+            light[s - 1].1 = pt.pdf(camera, qs);
+            if let Some(qs_minus) = qs_minus {
+                light[s - 2].1 = qs.pdf(camera, qs_minus);
+            }
+        }
+        // Emitters the light paths cannot start from are only found by camera paths.
+        if s == 0 && cam[t - 1].1 == 0.0 {
+            return 1.0;
+        }
+        if s == 1 && light[0].0 == 0.0 {
+            return 0.0;
+        }
+
+        // Specular vertices have no density and do not change the ratio.
+        let remap = |pdf: f64| if pdf != 0.0 { pdf } else { 1.0 };
+        let power = |r: f64| match heuristic {
+            MisHeuristic::Balance => r,
+            MisHeuristic::Power => r * r,
+        };
+        let mut sum = 0.0;
+        let mut ratio = 1.0;
+        for i in (1..t).rev() {
+            ratio *= remap(cam[i].1) / remap(cam[i].0);
+            if !cam[i].2 && !cam[i - 1].2 {
+                sum += power(ratio);
+            }
+        }
+        ratio = 1.0;
+        for i in (0..s).rev() {
+            ratio *= remap(light[i].1) / remap(light[i].0);
+            let delta_light = i > 0 && light[i - 1].2;
+            if !light[i].2 && !delta_light {
+                sum += power(ratio);
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+}
+
+/// Bidirectional path tracing of the camera ray `r`.
+///
+/// A light path starting at a point picked by `lights.random` from the camera and a
+/// camera path are connected at every pair of vertices, each connection weighted by
+/// the camera's `mis_heuristic` against all other ways to build the same path.
+/// Connections through the lens are pushed to `splats`, the rest is returned.
+pub fn bdpt(
+    camera: &Camera,
+    r: &Ray,
+    world: &Hittable,
+    lights: &Hittable,
+    aov: Option<&mut AovSample>,
+    splats: &mut Vec<LightSplat>,
+) -> Radiance {
+    let mut radiance = Radiance::default();
+    let max_depth = camera.max_depth;
+    if max_depth == 0 {
+        return radiance;
+    }
+    let importance = camera.importance(r.time);
+    let importance = importance.as_ref();
+    let origin = r.origin;
+
+    // Without importance the lens cannot be reached and counts as specular.
+    let mut camera_path = vec![Vertex::camera(r.origin, importance.is_none())];
+    let pdf_dir = importance.map_or(1.0, |camera| camera.pdf_dir(&r.direction));
+    let escaped = random_walk(
+        world,
+        r.clone(),
+        Vector3::repeat(1.0),
+        pdf_dir,
+        max_depth + 1,
+        &mut camera_path,
+    );
+    // The background cannot be sampled from the lights, camera paths find it alone.
+    if let Some(beta) = escaped {
+        radiance.add(
+            camera_path.len() - 1,
+            beta.component_mul(&camera.background),
+        );
+    }
+    if let (Some(aov), Some(rec)) = (aov, camera_path.get(1).and_then(|v| v.rec.as_ref())) {
+        record_aov(aov, r, rec);
+    }
+    let light_path = light_subpath(world, lights, &origin, r.time, max_depth);
+
+    for t in 1..=camera_path.len() {
+        // Next event estimation does not need the light path.
+        for s in 0..=light_path.len().max(1) {
+            let depth = s + t;
+            if depth < 2 || depth - 2 >= max_depth || (s == 1 && t == 1) {
+                continue;
+            }
+            let mut strategy = Strategy {
+                light_path: &light_path,
+                camera_path: &camera_path,
+                s,
+                t,
+                sampled: None,
+            };
+
+            let sampled;
+            let mut splat = None;
+            let contribution = if s == 0 {
+                // The camera path hit an emitter.
+                let pt = &camera_path[t - 1];
+                match pt.kind {
+                    VertexKind::Surface => pt.le(&camera_path[t - 2]).component_mul(&pt.beta),
+                    _ => Vector3::zeros(),
+                }
+            } else if t == 1 {
+                // The light path is connected to a point on the lens.
+                let qs = &light_path[s - 1];
+                let Some((lens, x, y, weight)) = importance
+                    .and_then(|camera| (!qs.delta).then(|| camera.sample_lens(&qs.p)).flatten())
+                else {
+                    continue;
+                };
+                sampled = Vertex::camera(lens, false);
+                splat = Some((x, y));
+                let cos = qs.normal.dot(&(lens - qs.p).normalize()).abs();
+                let c = qs.beta.component_mul(&qs.f(&sampled)) * weight * cos;
+                if c == Vector3::zeros() || !visible(world, &qs.p, &lens, r.time) {
+                    continue;
+                }
+                strategy.sampled = Some(&sampled);
+                c
+            } else if s == 1 {
+                // Next event estimation towards a point sampled on the lights.
+                let pt = &camera_path[t - 1];
+                if pt.delta || !pt.scatters() {
+                    continue;
+                }
+                let direction = lights.random(&pt.p);
+                let light_pdf = lights.pdf_value(&pt.p, &direction);
+                let Some(rec) = lights.hit(
+                    &Ray::new_with_time(pt.p, direction, r.time),
+                    &Interval::new(0.001, f64::INFINITY),
+                ) else {
+                    continue;
+                };
+                if light_pdf <= 0.0 {
+                    continue;
+                }
+                let le = rec.material.emitted(&rec.uv, &rec.p, &rec);
+                let pdf_fwd = emission_pdf(lights, &origin, &rec.p);
+                sampled = Vertex::light(rec, le / light_pdf, pdf_fwd);
+                let cos = pt.normal.dot(&direction.normalize()).abs();
+                let c = pt
+                    .beta
+                    .component_mul(&pt.f(&sampled))
+                    .component_mul(&sampled.beta)
+                    * cos;
+                if c == Vector3::zeros() || !visible(world, &pt.p, &sampled.p, r.time) {
+                    continue;
+                }
+                strategy.sampled = Some(&sampled);
+                c
+            } else {
+                // Both subpaths end on surfaces that are joined directly.
+                let (qs, pt) = (&light_path[s - 1], &camera_path[t - 1]);
+                if qs.delta || pt.delta {
+                    continue;
+                }
+                let d = pt.p - qs.p;
+                let distance_squared = d.norm_squared();
+                let g = (qs.normal.dot(&d) * pt.normal.dot(&d)).abs()
+                    / (distance_squared * distance_squared);
+                let c = qs
+                    .beta
+                    .component_mul(&qs.f(pt))
+                    .component_mul(&pt.f(qs))
+                    .component_mul(&pt.beta)
+                    * g;
+                if c == Vector3::zeros() || !visible(world, &qs.p, &pt.p, r.time) {
+                    continue;
+                }
+                c
+            };
+            if contribution == Vector3::zeros() {
+                continue;
+            }
+
+            let weight = strategy.mis_weight(importance, lights, &origin, camera.mis_heuristic);
+            match splat {
+                Some((x, y)) => splats.push(LightSplat {
+                    x,
+                    y,
+                    color: weight * contribution,
+                }),
+                None => radiance.add(depth - 2, weight * contribution),
+            }
+        }
+    }
+    radiance
+}
@@ -13,9 +13,11 @@ pub struct BVHNode {
     pub bbox: AABB,
     pub left: Box<Hittable>,
     pub right: Box<Hittable>,
+    pub object_ids: usize, // Object IDs taken by the primitives below the node
 }
 
 impl BVHNode {
+    /// BVH over the objects of `scene`, which keep the object IDs the scene gave them.
     pub fn new(scene: &mut Scene) -> Self {
         let len = scene.objects.len();
 
@@ -26,6 +28,7 @@ impl BVHNode {
         objects[start..end].iter().for_each(|obj| {
             bbox = AABB::merge(&bbox, obj.bbox());
         });
+        let object_ids = objects[start..end].iter().map(Hittable::object_ids).sum();
         let axis = bbox.longest_axis();
 
         let comparator = match axis {
@@ -41,6 +44,7 @@ impl BVHNode {
                 left: Box::new(objects[start].clone()),
                 right: Box::new(objects[start].clone()),
                 bbox,
+                object_ids,
             }
         } else if object_span == 2 {
             if comparator(&objects[start], &objects[start + 1]) == std::cmp::Ordering::Less {
@@ -48,12 +52,14 @@ impl BVHNode {
                     left: Box::new(objects[start].clone()),
                     right: Box::new(objects[start + 1].clone()),
                     bbox,
+                    object_ids,
                 }
             } else {
                 Self {
                     left: Box::new(objects[start + 1].clone()),
                     right: Box::new(objects[start].clone()),
                     bbox,
+                    object_ids,
                 }
             }
         } else {
@@ -62,7 +68,12 @@ impl BVHNode {
             let mid = start + object_span / 2;
             let left = Box::new(Hittable::BVH(Self::new_with_scene(objects, start, mid)));
             let right = Box::new(Hittable::BVH(Self::new_with_scene(objects, mid, end)));
-            Self { left, right, bbox }
+            Self {
+                left,
+                right,
+                bbox,
+                object_ids,
+            }
         }
     }
 
@@ -1,36 +1,105 @@
 use core::f64;
 use std::{
-    f64::{consts::PI, INFINITY, NAN},
+    f64::{consts::PI, NAN},
+    io,
     ops::Add,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
-use image::{ImageFormat, Rgb, RgbImage};
-use nalgebra::{clamp, Vector3};
-use rayon::iter::ParallelIterator;
+use image::ImageResult;
+use nalgebra::{Vector2, Vector3};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
 use crate::{
+    animation::Animation,
+    aov::{AovSample, Radiance},
+    aperture::{Aperture, ApertureShape},
+    checkpoint::{hash_debug, Checkpoint},
+    film::{Film, LightSplat},
+    filter::Filter,
     hit::Hittable,
-    pdf::{HittablePdf, MixturePdf},
+    integrator::{Integrator, MisHeuristic},
+    keyframe::{Interpolate, Keyframes},
+    mlt,
+    projection::{cubemap_direction, equirectangular_direction, fisheye_direction, Projection},
     ray::Ray,
+    sampler::{get_1d, get_2d, start_pixel_sample, Sampler},
     scene::Scene,
-    util::{random_f64, random_in_unit_sphere, random_unit_vector, sample_square, Interval},
+    shutter::ShutterCurve,
+    sppm,
+    tile::{
+        generate_tiles, CancelToken, CropWindow, ProgressCallback, Tile, TileOrder, TileProgress,
+    },
+    tonemap::ToneMapping,
+    util::{concentric_disk, random_unit_vector, sample_square},
 };
 
 pub struct Camera {
-    pub aspect_ratio: f64,        // Ratio of image width over height
-    pub image_width: usize,       // Rendered image width in pixel count
-    pub samples_per_pixel: usize, // Count of random samples for each pixel
-    pub max_depth: usize,         // Maximum number of ray bounces into scene
-    pub background: Vector3<f64>, // Background color for rays that miss
-    pub vfov: f64,                // Vertical field of view in degrees
-    pub lookfrom: Vector3<f64>,   // Camera origin
-    pub lookat: Vector3<f64>,     // Point camera is looking at
-    pub vup: Vector3<f64>,        // Camera up vector
-    pub defocus_angle: f64,       // Defocus blur angle
-    pub focus_dist: f64,          // Focus distance
-    image_height: usize,          // Rendered image height
-    sqrt_spp: usize,              // Square root of samples per pixel
-    recip_sqrt_spp: f64,          // Reciprocal of square root of samples per pixel
+    pub aspect_ratio: f64,                     // Ratio of image width over height
+    pub image_width: usize,                    // Rendered image width in pixel count
+    pub samples_per_pixel: usize,              // Count of random samples for each pixel
+    pub integrator: Integrator,                // Light transport algorithm
+    pub max_depth: usize,                      // Maximum number of ray bounces into scene
+    pub roulette_depth: usize,                 // Bounces before roulette may end paths, 0 disables
+    pub mis_heuristic: MisHeuristic,           // Weighting of the path and bidirectional strategies
+    pub background: Vector3<f64>,              // Background color for rays that miss
+    pub projection: Projection,                // Mapping from image positions to camera rays
+    pub vfov: f64,                             // Vertical field of view in degrees
+    pub lookfrom: Vector3<f64>,                // Camera origin
+    pub lookat: Vector3<f64>,                  // Point camera is looking at
+    pub vup: Vector3<f64>,                     // Camera up vector
+    pub defocus_angle: f64,                    // Defocus blur angle
+    pub focus_dist: f64,                       // Focus distance
+    pub lens_shift: Vector2<f64>,              // Viewport offset in its heights, x right and y up
+    pub aperture: Aperture,                    // Shape of the lens opening
+    pub shutter_open: f64,                     // Time the shutter opens
+    pub shutter_close: f64,                    // Time the shutter closes
+    pub shutter_curve: ShutterCurve,           // Shutter efficiency while open
+    pub motion: Keyframes<CameraPose>,         // Keyframed pose, overrides lookfrom, lookat and vup
+    pub samples_per_pass: usize,               // Samples per pixel added by each progressive pass
+    pub checkpoint_path: Option<PathBuf>,      // File progressive renders are checkpointed to
+    pub checkpoint_interval: usize,            // Number of passes between two checkpoints
+    pub tile_size: usize,                      // Edge length of the square tiles in pixels
+    pub tile_order: TileOrder,                 // Order in which tiles are rendered
+    pub on_progress: Option<ProgressCallback>, // Called after each finished tile
+    pub cancel: CancelToken,                   // Stops the render when cancelled
+    pub adaptive_threshold: f64,               // Relative error ending sampling of a pixel, 0 off
+    pub adaptive_min_samples: usize,           // Samples before a pixel may count as converged
+    pub seed: u64,                             // Seed of the per-sample random streams
+    pub sampler: Sampler,                      // Generator of the per-sample dimensions
+    pub filter: Filter,                        // Pixel reconstruction filter
+    pub tone_mapping: ToneMapping,             // Display transform of the returned film
+    pub aovs: bool,                            // Record first hit AOVs next to the beauty pass
+    pub crop_window: Option<CropWindow>,       // Region to render, the whole image when None
+    pub crop_full_frame: bool,                 // Return the full image, rendering only the crop
+    render_width: usize,                       // Rendered image width, whole faces for cubemaps
+    image_height: usize,                       // Rendered image height
+    frame_time: f64,                           // Start time of the animation frame being rendered
+    frame: Frame,                              // Camera geometry of the static pose
+}
+
+/// Position and orientation of the camera at one point in time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraPose {
+    pub lookfrom: Vector3<f64>,
+    pub lookat: Vector3<f64>,
+    pub vup: Vector3<f64>,
+}
+
+impl Interpolate for CameraPose {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        Self {
+            lookfrom: self.lookfrom.lerp(&other.lookfrom, t),
+            lookat: self.lookat.lerp(&other.lookat, t),
+            vup: self.vup.lerp(&other.vup, t),
+        }
+    }
+}
+
+/// Camera geometry derived from a pose.
+#[derive(Debug, Clone, Copy, Default)]
+struct Frame {
     center: Vector3<f64>,         // Camera center
     pixel00_loc: Vector3<f64>,    // Location of pixel 0, 0
     pixel_delta_u: Vector3<f64>,  // Offset to pixel to the right
@@ -42,226 +111,646 @@ pub struct Camera {
     defocus_disk_v: Vector3<f64>, // Defocus disk vertical axis
 }
 
+impl Frame {
+    /// Converts a direction from camera space (x right, y up, z forward) to world space.
+    fn camera_to_world(&self, d: Vector3<f64>) -> Vector3<f64> {
+        d.x * self.u + d.y * self.v - d.z * self.w
+    }
+}
+
+/// Perspective camera at one point in time as seen from the scene, used to connect
+/// light paths to the lens.
+///
+/// The importance is normalized over the whole image, so a light path reaching the
+/// lens contributes its full value to the single pixel it lands on.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CameraImportance {
+    frame: Frame,
+    width: usize,
+    height: usize,
+    focus_dist: f64,
+    film_area: f64,   // Viewport area at the focus distance
+    lens_radius: f64, // Radius of the disk shaped lens, 0 for a pinhole
+}
+
+impl CameraImportance {
+    /// Solid angle density of a camera ray leaving the lens along `direction`.
+    pub(crate) fn pdf_dir(&self, direction: &Vector3<f64>) -> f64 {
+        let cos = -direction.normalize().dot(&self.frame.w);
+        if cos <= 0.0 {
+            return 0.0;
+        }
+        self.focus_dist * self.focus_dist / (self.film_area * cos * cos * cos)
+    }
+
+    /// Samples a lens point seeing `p`. Returns the point, the pixel it lands on and
+    /// the importance arriving at `p` divided by the solid angle density of the
+    /// sample, or None when `p` is outside the image.
+    pub(crate) fn sample_lens(
+        &self,
+        p: &Vector3<f64>,
+    ) -> Option<(Vector3<f64>, usize, usize, f64)> {
+        let mut lens = self.frame.center;
+        if self.lens_radius > 0.0 {
+            let d = concentric_disk(&get_2d());
+            lens += self.lens_radius * (d.x * self.frame.u + d.y * self.frame.v);
+        }
+        let to_p = p - lens;
+        let distance_squared = to_p.norm_squared();
+        let cos = -to_p.dot(&self.frame.w) / distance_squared.sqrt();
+        if cos <= 0.0 {
+            return None;
+        }
+
+        // Raster position where the ray through the lens point meets the focus plane.
+        let on_plane =
+            lens + to_p * (self.focus_dist / -to_p.dot(&self.frame.w)) - self.frame.pixel00_loc;
+        let du = self.frame.pixel_delta_u;
+        let dv = self.frame.pixel_delta_v;
+        let x = (on_plane.dot(&du) / du.norm_squared() + 0.5).floor();
+        let y = (on_plane.dot(&dv) / dv.norm_squared() + 0.5).floor();
+        if x < 0.0 || y < 0.0 || x >= self.width as f64 || y >= self.height as f64 {
+            return None;
+        }
+
+        let weight = self.focus_dist * self.focus_dist
+            / (self.film_area * cos * cos * cos * distance_squared);
+        Some((lens, x as usize, y as usize, weight))
+    }
+}
+
 impl Default for Camera {
     fn default() -> Self {
         Self {
             aspect_ratio: 1.0,
             image_width: 100,
             samples_per_pixel: 10,
+            integrator: Integrator::default(),
             max_depth: 10,
+            roulette_depth: 3,
+            mis_heuristic: MisHeuristic::default(),
             background: Vector3::default(),
+            projection: Projection::default(),
             vfov: 90.0,
             lookfrom: Vector3::new(0.0, 0.0, -1.0),
             lookat: Vector3::new(0.0, 0.0, 0.0),
             vup: Vector3::new(0.0, 1.0, 0.0),
             defocus_angle: 0.0,
             focus_dist: 10.0,
+            lens_shift: Vector2::zeros(),
+            aperture: Aperture::default(),
+            shutter_open: 0.0,
+            shutter_close: 1.0,
+            shutter_curve: ShutterCurve::default(),
+            motion: Keyframes::default(),
+            samples_per_pass: 1,
+            checkpoint_path: None,
+            checkpoint_interval: 16,
+            tile_size: 16,
+            tile_order: TileOrder::default(),
+            on_progress: None,
+            cancel: CancelToken::default(),
+            adaptive_threshold: 0.0,
+            adaptive_min_samples: 16,
+            seed: 0,
+            sampler: Sampler::default(),
+            filter: Filter::default(),
+            tone_mapping: ToneMapping::default(),
+            aovs: false,
+            crop_window: None,
+            crop_full_frame: false,
+            render_width: 0,
             image_height: 0,
-            sqrt_spp: 10.0_f64.sqrt() as usize,
-            recip_sqrt_spp: 1.0 / (10.0_f64.sqrt()),
-            center: Vector3::default(),
-            pixel00_loc: Vector3::default(),
-            pixel_delta_u: Vector3::default(),
-            pixel_delta_v: Vector3::default(),
-            u: Vector3::default(),
-            v: Vector3::default(),
-            w: Vector3::default(),
-            defocus_disk_u: Vector3::default(),
-            defocus_disk_v: Vector3::default(),
+            frame_time: 0.0,
+            frame: Frame::default(),
         }
     }
 }
 
-fn linear_to_gamma(color: f64) -> f64 {
-    if color > 0.0 {
-        color.sqrt()
-    } else {
-        0.0
+impl Camera {
+    /// Renders the scene into a linear HDR framebuffer.
+    ///
+    /// Samples are accumulated in progressive passes of `samples_per_pass`, and the
+    /// film is written to `checkpoint_path` every `checkpoint_interval` passes.
+    /// Each pass is split into tiles, every finished tile is reported to `on_progress`.
+    /// With a non-zero `adaptive_threshold` pixels stop receiving samples once their
+    /// estimated relative error drops below it.
+    /// When `cancel` is triggered the render stops after the tiles in flight and
+    /// returns the partially rendered film.
+    /// With a `crop_window` only that region is rendered, and the returned film is
+    /// cropped to it unless `crop_full_frame` is set. Checkpoints always hold the
+    /// full frame.
+    /// `Integrator::PhotonMapping` renders `samples_per_pixel` whole image iterations
+    /// instead, and `Integrator::Metropolis` `samples_per_pixel` mutations per pixel,
+    /// both without tiles, checkpoints, adaptive sampling or AOVs.
+    pub fn render(&mut self, world: Hittable, lights: Hittable) -> Film {
+        self.initialize();
+
+        let mut film = Film::new(self.render_width, self.image_height);
+        film.tone_mapping = self.tone_mapping;
+        if self.aovs {
+            film.enable_aovs();
+        }
+        self.render_passes(film, &world, &lights)
     }
-}
 
-fn color_to_rgb(color: Vector3<f64>, sample_per_pixel: f64) -> Rgb<u8> {
-    let r = color.x;
-    let g = color.y;
-    let b = color.z;
-
-    let r = if r.is_nan() { 0.0 } else { r };
-    let g = if g.is_nan() { 0.0 } else { g };
-    let b = if b.is_nan() { 0.0 } else { b };
-
-    let scale = 1.0 / sample_per_pixel as f64;
-    let r = scale * r;
-    let g = scale * g;
-    let b = scale * b;
-
-    Rgb([
-        (256.0 * f64::clamp(linear_to_gamma(r), 0.0, 0.999)) as u8,
-        (256.0 * f64::clamp(linear_to_gamma(g), 0.0, 0.999)) as u8,
-        (256.0 * f64::clamp(linear_to_gamma(b), 0.0, 0.999)) as u8,
-    ])
-}
+    /// Renders the frames of `animation` into `dir`, named `frame_0001.png` and so on.
+    ///
+    /// The shutter interval is relative to the start of each frame, so the camera
+    /// `motion`, animated objects and keyframed materials blur over the part of the
+    /// frame the shutter is open. Each frame is checkpointed to a file of its own,
+    /// `checkpoint_path` with the frame number appended to its name.
+    pub fn render_animation<P: AsRef<Path>>(
+        &mut self,
+        animation: &Animation,
+        dir: P,
+        world: Hittable,
+        lights: Hittable,
+    ) -> ImageResult<()> {
+        let (vfov, focus_dist, defocus_angle) = (self.vfov, self.focus_dist, self.defocus_angle);
+        let checkpoint_path = self.checkpoint_path.clone();
+        let mut result = Ok(());
+        for frame in animation.start_frame..=animation.end_frame {
+            if self.cancel.is_cancelled() {
+                break;
+            }
+            let time = frame as f64;
+            self.frame_time = time;
+            self.vfov = animation.vfov.sample(time).unwrap_or(vfov);
+            self.focus_dist = animation.focus_dist.sample(time).unwrap_or(focus_dist);
+            self.defocus_angle = animation
+                .defocus_angle
+                .sample(time)
+                .unwrap_or(defocus_angle);
+
+            self.checkpoint_path = checkpoint_path
+                .as_ref()
+                .map(|path| animation.checkpoint_path(path, frame));
+
+            eprintln!("Frame {}", frame);
+            let film = self.render(world.clone(), lights.clone());
+            result = film.save(dir.as_ref().join(animation.file_name(frame)));
+            if result.is_err() {
+                break;
+            }
+        }
 
-impl Camera {
-    pub fn render(&mut self, world: Hittable, lights: Hittable) {
+        self.frame_time = 0.0;
+        self.checkpoint_path = checkpoint_path;
+        (self.vfov, self.focus_dist, self.defocus_angle) = (vfov, focus_dist, defocus_angle);
+        result
+    }
+
+    /// Continues a render from the checkpoint at `path` until every pixel has
+    /// `samples_per_pixel` samples.
+    ///
+    /// Fails if the checkpoint was written for a different camera or scene. The random
+    /// streams of the checkpoint are continued, `seed` is taken over from it.
+    pub fn resume<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        world: Hittable,
+        lights: Hittable,
+    ) -> io::Result<Film> {
         self.initialize();
 
-        // println!("P3\n{} {}\n255", self.image_width, self.image_height);
-        // let stdout = std::io::stdout();
-        let width = self.image_width;
-        let height = self.image_height;
-
-        let mut image = RgbImage::new(width as u32, height as u32);
-        image.par_enumerate_pixels_mut().for_each(|(i, j, pixel)| {
-            let mut color = Vector3::new(0.0, 0.0, 0.0);
-            for s_j in 0..self.sqrt_spp {
-                for s_i in 0..self.sqrt_spp {
-                    let ray = self.get_ray(i as i32, j as i32, s_i as i32, s_j as i32);
-                    color += self.ray_color(&ray, self.max_depth, &world, &lights);
+        let mut checkpoint = Checkpoint::load(path)?;
+        if checkpoint.scene_hash != self.scene_hash(&world, &lights) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "checkpoint was rendered from a different camera or scene",
+            ));
+        }
+
+        checkpoint.film.tone_mapping = self.tone_mapping;
+        self.seed = checkpoint.seed;
+        Ok(self.render_passes(checkpoint.film, &world, &lights))
+    }
+
+    /// Hash of everything that affects the rendered radiance except the sample count.
+    fn scene_hash(&self, world: &Hittable, lights: &Hittable) -> u64 {
+        hash_debug(&(
+            (
+                self.aspect_ratio,
+                self.image_width,
+                self.integrator,
+                self.max_depth,
+                self.roulette_depth,
+                self.mis_heuristic,
+                self.background,
+            ),
+            (
+                self.projection,
+                self.vfov,
+                self.lookfrom,
+                self.lookat,
+                self.vup,
+            ),
+            (
+                self.defocus_angle,
+                self.focus_dist,
+                self.lens_shift,
+                &self.aperture,
+            ),
+            (
+                self.frame_time,
+                self.shutter_open,
+                self.shutter_close,
+                &self.shutter_curve,
+            ),
+            &self.motion,
+            self.aovs,
+            self.sampler,
+            self.filter,
+            world,
+            lights,
+        ))
+    }
+
+    fn render_passes(&self, mut film: Film, world: &Hittable, lights: &Hittable) -> Film {
+        if let Integrator::PhotonMapping {
+            photons_per_pass,
+            initial_radius,
+        } = self.integrator
+        {
+            return sppm::render(self, film, world, lights, photons_per_pass, initial_radius);
+        }
+        if let Integrator::Metropolis {
+            bootstrap_samples,
+            chains,
+            mutation,
+        } = self.integrator
+        {
+            return mlt::render(
+                self,
+                film,
+                world,
+                lights,
+                bootstrap_samples,
+                chains,
+                mutation,
+            );
+        }
+        let scene_hash = self.scene_hash(world, lights);
+        let window = self.crop_bounds(film.width, film.height);
+        let tiles: Vec<Tile> = generate_tiles(
+            window.width(),
+            window.height(),
+            self.tile_size,
+            self.tile_order,
+        )
+        .into_iter()
+        .map(|tile| Tile {
+            x0: tile.x0 + window.x0,
+            y0: tile.y0 + window.y0,
+            x1: tile.x1 + window.x0,
+            y1: tile.y1 + window.y0,
+        })
+        .collect();
+        let mut pass = 0;
+
+        while window
+            .indices(film.width)
+            .any(|idx| !self.pixel_done(&film, idx))
+            && !self.cancel.is_cancelled()
+        {
+            // Workers pull tiles from a shared counter so they are started in tile order.
+            let next_tile = AtomicUsize::new(0);
+            let tiles_done = AtomicUsize::new(0);
+            let mut rendered: Vec<_> = (0..rayon::current_num_threads())
+                .into_par_iter()
+                .flat_map_iter(|_| {
+                    let mut rendered = vec![];
+                    loop {
+                        let k = next_tile.fetch_add(1, Ordering::Relaxed);
+                        if k >= tiles.len() || self.cancel.is_cancelled() {
+                            break;
+                        }
+                        rendered.push((k, self.render_tile(&tiles[k], &film, world, lights)));
+
+                        let progress = TileProgress {
+                            tile: tiles[k],
+                            pass,
+                            tiles_done: tiles_done.fetch_add(1, Ordering::Relaxed) + 1,
+                            tiles_total: tiles.len(),
+                        };
+                        if let Some(on_progress) = &self.on_progress {
+                            on_progress(&progress);
+                        }
+                    }
+                    rendered
+                })
+                .collect();
+
+            // Merge in tile order so the result does not depend on thread scheduling.
+            rendered.sort_by_key(|(k, _)| *k);
+            for (k, (tile_film, splats)) in &rendered {
+                let pad = self.tile_padding() as isize;
+                film.merge(
+                    tile_film,
+                    tiles[*k].x0 as isize - pad,
+                    tiles[*k].y0 as isize - pad,
+                );
+                for splat in splats {
+                    film.add_light_splat(splat);
+                }
+                // Every camera sample of the bidirectional integrator traces one light path.
+                if self.integrator == Integrator::Bidirectional {
+                    let samples: usize = tile_film.samples.iter().sum();
+                    film.light_paths += samples as f64 / (film.width * film.height) as f64;
                 }
             }
-            *pixel = color_to_rgb(color, self.samples_per_pixel as f64);
-        });
-        image.save("image.png").expect("Failed to save image");
-
-        // for j in 0..self.image_height {
-        //     eprintln!("\rScanlines remaining: {}", self.image_height - j);
-        //     for i in 0..self.image_width {
-        //         let mut pixel_color = Color::default();
-        //         for s_j in 0..self.sqrt_spp {
-        //             for s_i in 0..self.sqrt_spp {
-        //                 let r = self.get_ray(i as i32, j as i32, s_i as i32, s_j as i32);
-        //                 pixel_color += self.ray_color(&r, self.max_depth, world, lights);
-        //             }
-        //         }
-        //         pixel_color
-        //             .write_color(&mut stdout.lock(), self.samples_per_pixel)
-        //             .unwrap();
-        //     }
-        // }
+            pass += 1;
+
+            let finished = window
+                .indices(film.width)
+                .all(|idx| self.pixel_done(&film, idx));
+            eprint!("\rPasses: {}", pass);
+
+            if let Some(path) = &self.checkpoint_path {
+                if finished
+                    || self.cancel.is_cancelled()
+                    || pass % self.checkpoint_interval.max(1) == 0
+                {
+                    let checkpoint = Checkpoint {
+                        scene_hash,
+                        seed: self.seed,
+                        film: film.clone(),
+                    };
+                    if let Err(err) = checkpoint.save(path) {
+                        eprintln!("\nFailed to write checkpoint: {}", err);
+                    }
+                }
+            }
+        }
 
         eprintln!("\nDone.");
+        if self.crop_window.is_some() && !self.crop_full_frame {
+            return film.crop(window.x0, window.y0, window.width(), window.height());
+        }
+        film
+    }
+
+    /// Pixel bounds of the crop window, the whole image without one.
+    pub(crate) fn crop_bounds(&self, width: usize, height: usize) -> Tile {
+        match &self.crop_window {
+            Some(crop_window) => crop_window.bounds(width, height),
+            None => Tile {
+                x0: 0,
+                y0: 0,
+                x1: width,
+                y1: height,
+            },
+        }
+    }
+
+    /// Whether the pixel reached `samples_per_pixel` or, with adaptive sampling, converged.
+    fn pixel_done(&self, film: &Film, idx: usize) -> bool {
+        let samples = film.samples[idx];
+        samples >= self.samples_per_pixel
+            || (self.adaptive_threshold > 0.0
+                && samples >= self.adaptive_min_samples
+                && film.relative_error(idx) < self.adaptive_threshold)
+    }
+
+    /// Renders the next progressive pass of the pixels inside `tile` into a tile sized film.
+    /// The tile film is padded by the filter radius to catch samples splatted onto
+    /// neighbouring tiles. Light tracing splats can land anywhere on the image and are
+    /// returned separately.
+    fn render_tile(
+        &self,
+        tile: &Tile,
+        film: &Film,
+        world: &Hittable,
+        lights: &Hittable,
+    ) -> (Film, Vec<LightSplat>) {
+        let pad = self.tile_padding();
+        let mut tile_film = Film::new(tile.width() + 2 * pad, tile.height() + 2 * pad);
+        let mut splats = vec![];
+        if self.aovs {
+            tile_film.enable_aovs();
+        }
+        for j in tile.y0..tile.y1 {
+            for i in tile.x0..tile.x1 {
+                // Every pixel continues from its own sample count, so partially rendered
+                // passes can be resumed.
+                let idx = film.index(i, j);
+                if self.pixel_done(film, idx) {
+                    continue;
+                }
+                let start = film.samples[idx];
+                let end = (start + self.samples_per_pass.max(1)).min(self.samples_per_pixel);
+                for s in start..end {
+                    // Each sample gets its own sample dimensions and random stream, so the
+                    // result does not depend on which thread renders it.
+                    start_pixel_sample(self.sampler, i, j, s, self.samples_per_pixel, self.seed);
+                    let u = get_2d();
+                    let (dx, dy) = (u.x - 0.5, u.y - 0.5);
+                    let mut aov = AovSample::default();
+                    let radiance = match self.get_ray(i as f64 + dx, j as f64 + dy) {
+                        Some(ray) => self.integrator.radiance(
+                            self,
+                            &ray,
+                            world,
+                            lights,
+                            Some(&mut aov),
+                            &mut splats,
+                        ),
+                        None => Radiance::default(),
+                    };
+
+                    let (x, y) = (i - tile.x0 + pad, j - tile.y0 + pad);
+                    let color = radiance.total();
+                    tile_film.add_splat(x as f64 + dx, y as f64 + dy, color, &self.filter);
+                    tile_film.add_sample_stats(x, y, color);
+                    tile_film.add_aov_sample(x, y, &aov, &radiance);
+                }
+            }
+        }
+        (tile_film, splats)
+    }
+
+    /// Number of pixels the filter can reach beyond the pixel a sample was taken in.
+    fn tile_padding(&self) -> usize {
+        (self.filter.radius() - 0.5).ceil().max(0.0) as usize
     }
 
     fn initialize(&mut self) {
+        self.render_width = self.image_width;
         self.image_height = (self.image_width as f64 / self.aspect_ratio) as usize;
         self.image_height = if self.image_height < 1 {
             1
         } else {
             self.image_height
         };
-        self.sqrt_spp = (self.samples_per_pixel as f64).sqrt() as usize;
-        self.recip_sqrt_spp = 1.0 / (self.sqrt_spp as f64);
+        if let Projection::Cubemap { layout } = self.projection {
+            // Square faces, the width is rounded down to whole faces.
+            let (cols, rows) = layout.grid();
+            let face_size = (self.image_width / cols).max(1);
+            self.render_width = face_size * cols;
+            self.image_height = face_size * rows;
+        }
 
-        self.center = self.lookfrom;
+        self.frame = self.frame(&CameraPose {
+            lookfrom: self.lookfrom,
+            lookat: self.lookat,
+            vup: self.vup,
+        });
+    }
+
+    /// Computes the camera geometry for `pose`.
+    fn frame(&self, pose: &CameraPose) -> Frame {
+        let mut frame = Frame {
+            center: pose.lookfrom,
+            ..Default::default()
+        };
 
         // 确定视口尺寸。
-        let theta = self.vfov.to_radians();
-        let h = (theta / 2.0).tan();
-        let viewport_height = 2.0 * h * self.focus_dist;
-        let viewport_width = viewport_height * (self.image_width as f64 / self.image_height as f64);
+        let viewport_height = match self.projection {
+            Projection::Orthographic { height } => height,
+            _ => {
+                let theta = self.vfov.to_radians();
+                let h = (theta / 2.0).tan();
+                2.0 * h * self.focus_dist
+            }
+        };
+        let viewport_width =
+            viewport_height * (self.render_width as f64 / self.image_height as f64);
 
         // 计算相机坐标系的 u,v,w 单位基向量。
-        self.w = (self.lookfrom - self.lookat).normalize();
-        self.u = (self.vup.cross(&self.w)).normalize();
-        self.v = self.w.cross(&self.u);
+        frame.w = (pose.lookfrom - pose.lookat).normalize();
+        frame.u = (pose.vup.cross(&frame.w)).normalize();
+        frame.v = frame.w.cross(&frame.u);
 
         // 计算水平和垂直视口边缘上的向量。
-        let viewport_u = self.u * viewport_width;
-        let viewport_v = -self.v * viewport_height;
+        let viewport_u = frame.u * viewport_width;
+        let viewport_v = -frame.v * viewport_height;
 
         // 计算从像素到像素的水平和垂直增量向量。
-        self.pixel_delta_u = viewport_u / self.image_width as f64;
-        self.pixel_delta_v = viewport_v / self.image_height as f64;
+        frame.pixel_delta_u = viewport_u / self.render_width as f64;
+        frame.pixel_delta_v = viewport_v / self.image_height as f64;
 
         // 计算左上角像素的位置。
-        let viewport_upper_left =
-            self.center - (self.focus_dist * self.w) - (0.5 * viewport_u) - (0.5 * viewport_v);
-        self.pixel00_loc = viewport_upper_left + 0.5 * (self.pixel_delta_u + self.pixel_delta_v);
+        // The lens shift slides the viewport within its plane, keeping the view direction.
+        let shift = viewport_height * (self.lens_shift.x * frame.u + self.lens_shift.y * frame.v);
+        let viewport_upper_left = frame.center - (self.focus_dist * frame.w) + shift
+            - (0.5 * viewport_u)
+            - (0.5 * viewport_v);
+        frame.pixel00_loc = viewport_upper_left + 0.5 * (frame.pixel_delta_u + frame.pixel_delta_v);
 
         // 计算相机失焦盘的基向量。
         let defocus_radius = self.focus_dist * (self.defocus_angle / 2.0).to_radians().tan();
-        self.defocus_disk_u = self.u * defocus_radius;
-        self.defocus_disk_v = self.v * defocus_radius;
+        frame.defocus_disk_u = frame.u * defocus_radius;
+        frame.defocus_disk_v = frame.v * defocus_radius;
+        frame
     }
 
-    fn get_ray(&self, i: i32, j: i32, s_i: i32, s_j: i32) -> Ray {
-        // Get a randomly sampled camera ray for the pixel at location i,j.
-        let pixel_center =
-            self.pixel00_loc + i as f64 * self.pixel_delta_u + j as f64 * self.pixel_delta_v;
-        let pixel_sample = pixel_center + self.pixel_sample_square(s_i, s_j);
-
-        let ray_origin = if self.defocus_angle <= 0.0 {
-            self.center
-        } else {
-            self.defocus_disk_sample()
-        };
-        let ray_direction = pixel_sample - ray_origin;
-        let ray_time = random_f64();
-
-        Ray::new_with_time(ray_origin, ray_direction, ray_time)
+    /// Camera geometry at `time`.
+    fn frame_at(&self, time: f64) -> Frame {
+        match self.motion.sample(time) {
+            Some(pose) => self.frame(&pose),
+            None => self.frame,
+        }
     }
 
-    fn pixel_sample_square(&self, s_i: i32, s_j: i32) -> Vector3<f64> {
-        // Returns a random point in the square surrounding a pixel at the origin.
-        let px = -0.5 + self.recip_sqrt_spp * (s_i as f64 + random_f64());
-        let py = -0.5 + self.recip_sqrt_spp * (s_j as f64 + random_f64());
-        px * self.pixel_delta_u + py * self.pixel_delta_v
+    /// Importance of the camera at `time` for connecting light paths to it. None
+    /// unless the camera is a pinhole or a thin lens with an unvignetted disk
+    /// aperture under a perspective projection.
+    pub(crate) fn importance(&self, time: f64) -> Option<CameraImportance> {
+        let disk_lens = self.aperture.shape == ApertureShape::Disk && self.aperture.cat_eye <= 0.0;
+        if self.projection != Projection::Perspective || (self.defocus_angle > 0.0 && !disk_lens) {
+            return None;
+        }
+        let frame = self.frame_at(time);
+        let (width, height) = (self.render_width, self.image_height);
+        Some(CameraImportance {
+            frame,
+            width,
+            height,
+            focus_dist: self.focus_dist,
+            film_area: frame.pixel_delta_u.norm()
+                * frame.pixel_delta_v.norm()
+                * (width * height) as f64,
+            lens_radius: frame.defocus_disk_u.norm(),
+        })
     }
 
-    fn defocus_disk_sample(&self) -> Vector3<f64> {
-        // Returns a random point in the defocus disk.
-        let p = random_in_unit_sphere();
-        self.center + p.x * self.defocus_disk_u + p.y * self.defocus_disk_v
+    /// Point in time within the open shutter, distributed by the shutter curve.
+    pub(crate) fn sample_time(&self) -> f64 {
+        self.frame_time
+            + self.shutter_open
+            + (self.shutter_close - self.shutter_open) * self.shutter_curve.sample(get_1d())
     }
 
-    fn ray_color(
-        &self,
-        r: &Ray,
-        depth: usize,
-        world: &Hittable,
-        lights: &Hittable,
-    ) -> Vector3<f64> {
-        // 如果我们超过了光线反弹限制，就不再收集光线。
-        if depth == 0 {
-            return Vector3::default();
-        }
-
-        // 如果光线没有击中了世界中的任何东西，则返回背景颜色。
-        match world.hit(r, &Interval::new(0.001, INFINITY)) {
-            Some(rec) => {
-                let mat = rec.material;
-                let color_from_emission = mat.emitted(&rec.uv, &rec.p,  &rec);
-
-                match mat.scatter(r, &rec) {
-                    Some(srec) => {
-                        if srec.skip_pdf {
-                            return srec.attenuation.component_mul(&self.ray_color(
-                                &srec.skip_pdf_ray,
-                                depth - 1,
-                                world,
-                                lights,
-                            ));
-                        }
+    pub(crate) fn get_ray(&self, x: f64, y: f64) -> Option<Ray> {
+        // Get a camera ray through the continuous image position x,y, pixel centers
+        // sitting at integer coordinates. None where the projection does not cover
+        // the image.
+        let ray_time = self.sample_time();
+        let frame = self.frame_at(ray_time);
 
-                        let light_pdf =
-                            crate::pdf::PDF::Hittable(Box::new(HittablePdf::new(lights, rec.p)));
-                        let mixed_pdf = MixturePdf::new(&light_pdf, &srec.pdf);
+        let (ray_origin, ray_direction) = match self.projection {
+            Projection::Perspective | Projection::Orthographic { .. } => {
+                self.planar_ray(&frame, x, y)?
+            }
+            // Panoramic projections are taken from the camera center, without a lens.
+            Projection::Equirectangular => {
+                let (s, t) = self.image_uv(x, y);
+                (
+                    frame.center,
+                    frame.camera_to_world(equirectangular_direction(s, t)),
+                )
+            }
+            Projection::Fisheye { mapping, fov } => {
+                let radius = 0.5 * self.render_width.min(self.image_height) as f64;
+                let fx = (x + 0.5 - 0.5 * self.render_width as f64) / radius;
+                let fy = (0.5 * self.image_height as f64 - y - 0.5) / radius;
+                let direction = fisheye_direction(mapping, fov, fx, fy)?;
+                (frame.center, frame.camera_to_world(direction))
+            }
+            Projection::Cubemap { layout } => {
+                let (s, t) = self.image_uv(x, y);
+                let direction = cubemap_direction(layout, s, t)?;
+                (frame.center, frame.camera_to_world(direction))
+            }
+        };
 
-                        let scattered = Ray::new_with_time(rec.p, mixed_pdf.generate(), r.time);
-                        let pdf = mixed_pdf.value(&scattered.direction);
+        Some(Ray::new_with_time(ray_origin, ray_direction, ray_time))
+    }
 
-                        let scattering_pdf = mat.scattering_pdf(&r, &scattered, &rec);
-                        // eprintln!("pdf: {}, scattering_pdf: {}", pdf, scattering_pdf);
-                        let sample_color=self.ray_color(&scattered, depth - 1, world, lights);
-                        let color_from_scatter =
-                            (srec.attenuation.component_mul(&sample_color) * scattering_pdf) / pdf;
+    /// Origin and direction of a ray through the viewport of a planar projection.
+    /// None when the lens barrel blocks the ray.
+    fn planar_ray(&self, frame: &Frame, x: f64, y: f64) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        let pixel_sample = frame.pixel00_loc + x * frame.pixel_delta_u + y * frame.pixel_delta_v;
 
-                        color_from_emission + color_from_scatter
-                    }
-                    None => return color_from_emission,
-                }
+        let lens_offset = if self.defocus_angle <= 0.0 {
+            Vector3::zeros()
+        } else {
+            let p = self.aperture.shape.sample(&get_2d());
+            let (w, h) = (self.render_width as f64, self.image_height as f64);
+            let image_pos = Vector2::new(x + 0.5 - 0.5 * w, 0.5 * h - y - 0.5) / (0.5 * w.hypot(h));
+            if self.aperture.vignetted(&p, &image_pos) {
+                return None;
             }
-            None => self.background,
-        }
+            p.x * frame.defocus_disk_u + p.y * frame.defocus_disk_v
+        };
+        let ray_origin = match self.projection {
+            // Parallel rays start on the plane through the camera center, the lens
+            // offset tilts them to meet again at the focus plane.
+            Projection::Orthographic { .. } => {
+                pixel_sample + self.focus_dist * frame.w + lens_offset
+            }
+            _ => frame.center + lens_offset,
+        };
+        Some((ray_origin, pixel_sample - ray_origin))
+    }
+
+    /// Maps the continuous image position x,y to [0,1]², with 0,0 at the top left
+    /// corner of the image.
+    fn image_uv(&self, x: f64, y: f64) -> (f64, f64) {
+        (
+            (x + 0.5) / self.render_width as f64,
+            (y + 0.5) / self.image_height as f64,
+        )
     }
 }
@@ -0,0 +1,216 @@
+use std::{
+    fmt::{self, Debug, Write as _},
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use nalgebra::Vector3;
+
+use crate::{aov::AovBuffers, film::Film};
+
+const MAGIC: &[u8; 8] = b"PBRTCKPT";
+const VERSION: u32 = 1;
+
+/// Bytes of the magic, version, scene hash, seed and film size.
+const HEADER_BYTES: u64 = 8 + 4 + 4 * 8;
+/// Bytes stored for every pixel of the film, and for its AOVs when present.
+const PIXEL_BYTES: u64 = 11 * 8;
+const AOV_PIXEL_BYTES: u64 = 21 * 8;
+/// Bytes of the light path count and the AOV flag after the pixels.
+const TRAILER_BYTES: u64 = 2 * 8;
+
+/// Snapshot of a progressive render that can be resumed later.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub scene_hash: u64, // Hash of the camera and scene the film was rendered from
+    pub seed: u64,       // Seed of the random streams, they continue from the sample counts
+    pub film: Film,
+}
+
+/// FNV-1a hasher fed with the `Debug` representation of a value.
+struct DebugHasher(u64);
+
+impl fmt::Write for DebugHasher {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+        Ok(())
+    }
+}
+
+/// Hashes the `Debug` representation of `value`, which is stable across runs
+/// unlike `std::hash::DefaultHasher`.
+pub fn hash_debug<T: Debug>(value: &T) -> u64 {
+    let mut hasher = DebugHasher(0xcbf29ce484222325);
+    write!(hasher, "{:?}", value).expect("Hashing never fails");
+    hasher.0
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_f64<W: Write>(writer: &mut W, value: f64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_vec<W: Write>(writer: &mut W, value: &Vector3<f64>) -> io::Result<()> {
+    write_f64(writer, value.x)?;
+    write_f64(writer, value.y)?;
+    write_f64(writer, value.z)
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_f64<R: Read>(reader: &mut R) -> io::Result<f64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(f64::from_le_bytes(buf))
+}
+
+fn read_vec<R: Read>(reader: &mut R) -> io::Result<Vector3<f64>> {
+    Ok(Vector3::new(
+        read_f64(reader)?,
+        read_f64(reader)?,
+        read_f64(reader)?,
+    ))
+}
+
+impl Checkpoint {
+    /// Writes the checkpoint to `path`. The data goes to a temporary file first, the
+    /// path with `.partial` appended, so an interrupted write never clobbers the
+    /// previous checkpoint.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp_path = path.as_os_str().to_os_string();
+        tmp_path.push(".partial");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            writer.write_all(MAGIC)?;
+            writer.write_all(&VERSION.to_le_bytes())?;
+            write_u64(&mut writer, self.scene_hash)?;
+            write_u64(&mut writer, self.seed)?;
+            write_u64(&mut writer, self.film.width as u64)?;
+            write_u64(&mut writer, self.film.height as u64)?;
+            let film = &self.film;
+            for idx in 0..film.width * film.height {
+                write_vec(&mut writer, &film.color[idx])?;
+                write_f64(&mut writer, film.weight[idx])?;
+                write_f64(&mut writer, film.abs_weight[idx])?;
+                write_f64(&mut writer, film.luminance[idx])?;
+                write_f64(&mut writer, film.luminance_sq[idx])?;
+                write_u64(&mut writer, film.samples[idx] as u64)?;
+                write_vec(&mut writer, &film.light[idx])?;
+            }
+            write_f64(&mut writer, film.light_paths)?;
+
+            write_u64(&mut writer, film.aovs.is_some() as u64)?;
+            if let Some(aovs) = &film.aovs {
+                for idx in 0..film.width * film.height {
+                    write_vec(&mut writer, &aovs.albedo[idx])?;
+                    write_vec(&mut writer, &aovs.normal[idx])?;
+                    write_vec(&mut writer, &aovs.position[idx])?;
+                    write_f64(&mut writer, aovs.depth[idx])?;
+                    // Pixels without a hit are stored as u64::MAX.
+                    write_u64(
+                        &mut writer,
+                        aovs.object_id[idx].map_or(u64::MAX, |id| id as u64),
+                    )?;
+                    write_vec(&mut writer, &aovs.emission[idx])?;
+                    write_vec(&mut writer, &aovs.direct[idx])?;
+                    write_vec(&mut writer, &aovs.indirect[idx])?;
+                    write_u64(&mut writer, aovs.hits[idx] as u64)?;
+                }
+            }
+            writer.flush()?;
+        }
+        fs::rename(tmp_path, path)
+    }
+
+    /// Reads a checkpoint written by `save`. Fails with `InvalidData` if the file is
+    /// not one, or its size does not match the film size in its header.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a checkpoint file"));
+        }
+        let mut version = [0; 4];
+        reader.read_exact(&mut version)?;
+        if u32::from_le_bytes(version) != VERSION {
+            return Err(invalid_data("unsupported checkpoint version"));
+        }
+
+        let scene_hash = read_u64(&mut reader)?;
+        let seed = read_u64(&mut reader)?;
+        let width = read_u64(&mut reader)?;
+        let height = read_u64(&mut reader)?;
+
+        // The film is only allocated once the file is known to hold all its pixels.
+        let pixels = width
+            .checked_mul(height)
+            .filter(|&pixels| usize::try_from(pixels).is_ok())
+            .ok_or_else(|| invalid_data("checkpoint film size out of range"))?;
+        let len = |pixel_bytes: u64| {
+            pixels
+                .checked_mul(pixel_bytes)
+                .and_then(|len| len.checked_add(HEADER_BYTES + TRAILER_BYTES))
+        };
+        if len(PIXEL_BYTES) != Some(file_len)
+            && len(PIXEL_BYTES + AOV_PIXEL_BYTES) != Some(file_len)
+        {
+            return Err(invalid_data("checkpoint size does not match its film size"));
+        }
+        let (width, height) = (width as usize, height as usize);
+
+        let mut film = Film::new(width, height);
+        for idx in 0..width * height {
+            film.color[idx] = read_vec(&mut reader)?;
+            film.weight[idx] = read_f64(&mut reader)?;
+            film.abs_weight[idx] = read_f64(&mut reader)?;
+            film.luminance[idx] = read_f64(&mut reader)?;
+            film.luminance_sq[idx] = read_f64(&mut reader)?;
+            film.samples[idx] = read_u64(&mut reader)? as usize;
+            film.light[idx] = read_vec(&mut reader)?;
+        }
+        film.light_paths = read_f64(&mut reader)?;
+
+        if read_u64(&mut reader)? != 0 {
+            let mut aovs = AovBuffers::new(width * height);
+            for idx in 0..width * height {
+                aovs.albedo[idx] = read_vec(&mut reader)?;
+                aovs.normal[idx] = read_vec(&mut reader)?;
+                aovs.position[idx] = read_vec(&mut reader)?;
+                aovs.depth[idx] = read_f64(&mut reader)?;
+                let id = read_u64(&mut reader)?;
+                aovs.object_id[idx] = (id != u64::MAX).then_some(id as usize);
+                aovs.emission[idx] = read_vec(&mut reader)?;
+                aovs.direct[idx] = read_vec(&mut reader)?;
+                aovs.indirect[idx] = read_vec(&mut reader)?;
+                aovs.hits[idx] = read_u64(&mut reader)? as usize;
+            }
+            film.aovs = Some(aovs);
+        }
+
+        Ok(Self {
+            scene_hash,
+            seed,
+            film,
+        })
+    }
+}
@@ -0,0 +1,283 @@
+use std::path::Path;
+
+use image::{ImageResult, Rgb, RgbImage};
+use nalgebra::Vector3;
+
+use crate::{
+    aov::{Aov, AovBuffers, AovSample, Radiance},
+    filter::Filter,
+    output::{self, OutputFormat},
+    tonemap::ToneMapping,
+    util::luminance,
+};
+
+/// Fraction of the absolute filter weight sum of a pixel below which its weight
+/// sum is clamped when resolving it.
+const MIN_WEIGHT_FRACTION: f64 = 0.1;
+
+/// Linear HDR framebuffer produced by `Camera::render`.
+///
+/// Each pixel stores the unclamped, filter weighted sum of the radiance samples
+/// splatted onto it together with the sum of their weights, so the image can be
+/// resolved, post-processed or written out in any format after rendering.
+/// Sample counts and luminance statistics are tracked for the pixel a sample was
+/// taken in, regardless of which pixels it was splatted onto.
+///
+/// Filters with negative lobes, Mitchell and Lanczos, can leave a pixel with a
+/// weight sum near or below zero. Such sums are clamped to a tenth of the sum of
+/// the absolute weights when the pixel is resolved, rather than dividing the noise
+/// by a vanishing weight. Pixels only reached by positive weights are never clamped.
+///
+/// Light tracing contributions of bidirectional integrators land on arbitrary
+/// pixels and are kept apart in `light`, normalized by the number of light paths
+/// traced per pixel of the whole image.
+#[derive(Debug, Clone)]
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    pub color: Vec<Vector3<f64>>, // Per-pixel sum of weighted radiance samples
+    pub weight: Vec<f64>,         // Per-pixel sum of filter weights
+    pub abs_weight: Vec<f64>,     // Per-pixel sum of absolute filter weights
+    pub luminance: Vec<f64>,      // Per-pixel sum of sample luminance
+    pub luminance_sq: Vec<f64>,   // Per-pixel sum of squared sample luminance
+    pub samples: Vec<usize>,      // Per-pixel sample count
+    pub light: Vec<Vector3<f64>>, // Per-pixel sum of light tracing splats
+    pub light_paths: f64,         // Light paths traced per pixel, divides `light`
+    pub aovs: Option<AovBuffers>, // Output variables, only recorded when enabled
+    pub tone_mapping: ToneMapping, // Display transform used for 8-bit output
+}
+
+/// Light tracing contribution landing on the pixel at x,y.
+#[derive(Debug, Clone, Copy)]
+pub struct LightSplat {
+    pub x: usize,
+    pub y: usize,
+    pub color: Vector3<f64>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            color: vec![Vector3::zeros(); width * height],
+            weight: vec![0.0; width * height],
+            abs_weight: vec![0.0; width * height],
+            luminance: vec![0.0; width * height],
+            luminance_sq: vec![0.0; width * height],
+            samples: vec![0; width * height],
+            light: vec![Vector3::zeros(); width * height],
+            light_paths: 0.0,
+            aovs: None,
+            tone_mapping: ToneMapping::default(),
+        }
+    }
+
+    /// Starts recording the arbitrary output variables of every sample.
+    pub fn enable_aovs(&mut self) {
+        self.aovs = Some(AovBuffers::new(self.width * self.height));
+    }
+
+    pub fn index(&self, x: usize, y: usize) -> usize {
+        y * self.width + x
+    }
+
+    /// Adds a sample that only counts for the pixel at x,y.
+    pub fn add_sample(&mut self, x: usize, y: usize, color: Vector3<f64>) {
+        let idx = self.index(x, y);
+        self.color[idx] += color;
+        self.weight[idx] += 1.0;
+        self.abs_weight[idx] += 1.0;
+        self.add_sample_stats(x, y, color);
+    }
+
+    /// Counts a sample taken for the pixel at x,y without adding its radiance.
+    pub fn add_sample_stats(&mut self, x: usize, y: usize, color: Vector3<f64>) {
+        let idx = self.index(x, y);
+        let l = luminance(&color);
+        self.luminance[idx] += l;
+        self.luminance_sq[idx] += l * l;
+        self.samples[idx] += 1;
+    }
+
+    /// Splats a sample at the continuous position x,y onto all pixels within the
+    /// filter radius. Pixel centers sit at integer coordinates.
+    pub fn add_splat(&mut self, x: f64, y: f64, color: Vector3<f64>, filter: &Filter) {
+        let radius = filter.radius();
+        let x0 = (x - radius).ceil().max(0.0) as usize;
+        let y0 = (y - radius).ceil().max(0.0) as usize;
+        let x1 = (x + radius).floor().min(self.width as f64 - 1.0);
+        let y1 = (y + radius).floor().min(self.height as f64 - 1.0);
+        if x1 < 0.0 || y1 < 0.0 {
+            return;
+        }
+
+        for py in y0..=y1 as usize {
+            for px in x0..=x1 as usize {
+                let weight = filter.evaluate(px as f64 - x, py as f64 - y);
+                if weight != 0.0 {
+                    let idx = self.index(px, py);
+                    self.color[idx] += weight * color;
+                    self.weight[idx] += weight;
+                    self.abs_weight[idx] += weight.abs();
+                }
+            }
+        }
+    }
+
+    pub fn add_light_splat(&mut self, splat: &LightSplat) {
+        let idx = self.index(splat.x, splat.y);
+        self.light[idx] += splat.color;
+    }
+
+    pub fn add_aov_sample(&mut self, x: usize, y: usize, aov: &AovSample, radiance: &Radiance) {
+        let idx = self.index(x, y);
+        if let Some(aovs) = &mut self.aovs {
+            aovs.add_sample(idx, aov, radiance);
+        }
+    }
+
+    /// Adds the samples of `other`, whose top left pixel sits at x0,y0 in this film.
+    /// Pixels falling outside of this film are ignored. `light_paths` is left alone,
+    /// it describes the whole image rather than single pixels.
+    pub fn merge(&mut self, other: &Film, x0: isize, y0: isize) {
+        for y in 0..other.height {
+            for x in 0..other.width {
+                let (dst_x, dst_y) = (x0 + x as isize, y0 + y as isize);
+                if dst_x < 0
+                    || dst_y < 0
+                    || dst_x >= self.width as isize
+                    || dst_y >= self.height as isize
+                {
+                    continue;
+                }
+                let src = other.index(x, y);
+                let dst = self.index(dst_x as usize, dst_y as usize);
+                self.color[dst] += other.color[src];
+                self.weight[dst] += other.weight[src];
+                self.abs_weight[dst] += other.abs_weight[src];
+                self.luminance[dst] += other.luminance[src];
+                self.luminance_sq[dst] += other.luminance_sq[src];
+                self.samples[dst] += other.samples[src];
+                self.light[dst] += other.light[src];
+                if let (Some(aovs), Some(other_aovs)) = (&mut self.aovs, &other.aovs) {
+                    aovs.merge_pixel(dst, other_aovs, src);
+                }
+            }
+        }
+    }
+
+    /// Copies the `width`x`height` region with its top left pixel at x0,y0 into a new film.
+    pub fn crop(&self, x0: usize, y0: usize, width: usize, height: usize) -> Film {
+        let mut film = Film::new(width, height);
+        film.tone_mapping = self.tone_mapping;
+        film.light_paths = self.light_paths;
+        if self.aovs.is_some() {
+            film.enable_aovs();
+        }
+        film.merge(self, -(x0 as isize), -(y0 as isize));
+        film
+    }
+
+    /// Returns the filtered linear radiance of the pixel at x,y.
+    pub fn pixel(&self, x: usize, y: usize) -> Vector3<f64> {
+        let idx = self.index(x, y);
+        let finite = |c: f64| if c.is_nan() { 0.0 } else { c };
+        let mut color = Vector3::zeros();
+        let weight = self.weight[idx].max(MIN_WEIGHT_FRACTION * self.abs_weight[idx]);
+        if weight > 0.0 {
+            color += self.color[idx].map(finite) / weight;
+        }
+        if self.light_paths > 0.0 {
+            color += self.light[idx].map(finite) / self.light_paths;
+        }
+        color
+    }
+
+    /// Estimated relative standard error of the pixel mean, used by adaptive sampling.
+    pub fn relative_error(&self, idx: usize) -> f64 {
+        let n = self.samples[idx] as f64;
+        if n < 2.0 {
+            return f64::INFINITY;
+        }
+        let mean = self.luminance[idx] / n;
+        let variance = ((self.luminance_sq[idx] / n - mean * mean) * n / (n - 1.0)).max(0.0);
+        (variance / n).sqrt() / mean.abs().max(1e-4)
+    }
+
+    /// Visualizes the per-pixel sample counts, from blue for the fewest samples
+    /// over green to red for the most.
+    pub fn sample_heatmap(&self) -> RgbImage {
+        let min = self.samples.iter().copied().min().unwrap_or(0) as f64;
+        let max = self.samples.iter().copied().max().unwrap_or(0) as f64;
+        RgbImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+            let n = self.samples[self.index(x as usize, y as usize)] as f64;
+            let t = if max > min {
+                (n - min) / (max - min)
+            } else {
+                0.0
+            };
+            let (r, g, b) = if t < 0.5 {
+                (0.0, 2.0 * t, 1.0 - 2.0 * t)
+            } else {
+                (2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+            };
+            Rgb([(255.0 * r) as u8, (255.0 * g) as u8, (255.0 * b) as u8])
+        })
+    }
+
+    pub fn save_sample_heatmap<P: AsRef<Path>>(&self, path: P) -> ImageResult<()> {
+        self.sample_heatmap().save(path)
+    }
+
+    /// Quantizes the film to 8-bit sRGB through `tone_mapping`.
+    pub fn to_rgb_image(&self) -> RgbImage {
+        RgbImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+            self.tone_mapping.to_rgb(self.pixel(x as usize, y as usize))
+        })
+    }
+
+    /// Extracts a single output variable as its own film.
+    pub fn aov(&self, aov: Aov) -> Option<Film> {
+        self.aovs
+            .as_ref()
+            .map(|aovs| aovs.film(aov, self.width, self.height, &self.samples))
+    }
+
+    /// Writes every recorded output variable next to the beauty image at `path`,
+    /// e.g. `image.albedo.exr` and `image.normal.exr` for `image.exr`.
+    pub fn save_aovs<P: AsRef<Path>>(&self, path: P) -> ImageResult<()> {
+        let path = path.as_ref();
+        let format = OutputFormat::from_path(path)?;
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let ext = path.extension().unwrap_or_default().to_string_lossy();
+
+        for aov in Aov::ALL {
+            if let Some(film) = self.aov(aov) {
+                let aov_path = path.with_file_name(format!("{}.{}.{}", stem, aov.name(), ext));
+                film.save_with_format(aov_path, format)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the film to `path`, the image format is deduced from the file extension.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> ImageResult<()> {
+        let format = OutputFormat::from_path(&path)?;
+        self.save_with_format(path, format)
+    }
+
+    pub fn save_with_format<P: AsRef<Path>>(
+        &self,
+        path: P,
+        format: OutputFormat,
+    ) -> ImageResult<()> {
+        match format {
+            OutputFormat::Ldr(format) => self.to_rgb_image().save_with_format(path, format),
+            OutputFormat::ExrHalf => output::write_exr_half(self, path),
+            OutputFormat::ExrFloat => output::write_exr_float(self, path),
+            OutputFormat::Hdr => output::write_hdr(self, path),
+            OutputFormat::Pfm => output::write_pfm(self, path),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::f64::consts::PI;
+
+/// Pixel reconstruction filter, camera samples are splatted onto every pixel whose
+/// center lies within `radius` pixels of the sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Box { radius: f64 },
+    Triangle { radius: f64 },
+    Gaussian { radius: f64, alpha: f64 },
+    Mitchell { radius: f64, b: f64, c: f64 },
+    Lanczos { radius: f64, tau: f64 },
+}
+
+impl Default for Filter {
+    /// A box covering exactly one pixel, every sample only counts for its own pixel.
+    fn default() -> Self {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+fn gaussian(x: f64, alpha: f64, radius: f64) -> f64 {
+    ((-alpha * x * x).exp() - (-alpha * radius * radius).exp()).max(0.0)
+}
+
+fn mitchell(x: f64, b: f64, c: f64) -> f64 {
+    let x = x.abs();
+    if x > 2.0 {
+        0.0
+    } else if x > 1.0 {
+        ((-b - 6.0 * c) * x * x * x
+            + (6.0 * b + 30.0 * c) * x * x
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    } else {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+            + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+            + (6.0 - 2.0 * b))
+            / 6.0
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+impl Filter {
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Triangle { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius, .. } => radius,
+        }
+    }
+
+    /// Filter weight along one axis at an offset of x pixels from the pixel center.
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let radius = self.radius();
+        if x.abs() > radius {
+            return 0.0;
+        }
+        match *self {
+            Filter::Box { .. } => 1.0,
+            Filter::Triangle { .. } => radius - x.abs(),
+            Filter::Gaussian { alpha, .. } => gaussian(x, alpha, radius),
+            Filter::Mitchell { b, c, .. } => mitchell(2.0 * x / radius, b, c),
+            Filter::Lanczos { tau, .. } => sinc(x) * sinc(x / tau),
+        }
+    }
+
+    /// Weight of a sample at an offset of x,y pixels from the pixel center.
+    pub fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+}
@@ -14,6 +14,8 @@ pub struct HitRecord<'a> {
     pub uv: Vector2<f64>,
     pub material: &'a Material,
     pub trace:bool,
+    pub time: f64, // Time of the ray that hit, where keyframed materials are evaluated
+    pub object_id: usize, // ID of the hit primitive, unique within the outermost scene
 }
 
 impl HitRecord<'_> {
@@ -83,4 +85,50 @@ impl Hittable {
             Hittable::ConstantMedium(_) => todo!(),
         }
     }
+
+    /// Number of object IDs taken by the primitives of this object.
+    pub fn object_ids(&self) -> usize {
+        match self {
+            Hittable::Sphere(_) | Hittable::Quad(_) => 1,
+            Hittable::BVH(node) => node.object_ids,
+            Hittable::PrefabScene(scene) => scene.object_ids,
+            Hittable::Rotate(obj) => obj.object.object_ids(),
+            Hittable::Translate(obj) => obj.object.object_ids(),
+            Hittable::ConstantMedium(medium) => medium.boundary.object_ids(),
+        }
+    }
+
+    /// Shifts the object IDs of all primitives of this object by `offset`.
+    pub fn offset_object_ids(&mut self, offset: usize) {
+        match self {
+            Hittable::Sphere(obj) => obj.object_id += offset,
+            Hittable::Quad(obj) => obj.object_id += offset,
+            Hittable::BVH(node) => {
+                node.left.offset_object_ids(offset);
+                node.right.offset_object_ids(offset);
+            }
+            Hittable::PrefabScene(scene) => {
+                for obj in &mut scene.objects {
+                    obj.offset_object_ids(offset);
+                }
+            }
+            Hittable::Rotate(obj) => obj.object.offset_object_ids(offset),
+            Hittable::Translate(obj) => obj.object.offset_object_ids(offset),
+            Hittable::ConstantMedium(medium) => medium.boundary.offset_object_ids(offset),
+        }
+    }
+
+    /// Point picked uniformly by area on the surface at `time`, facing along its
+    /// outward normal, and the area density it was picked with. Lists pick one of
+    /// their objects uniformly first. None where the surface cannot be sampled.
+    pub fn sample_point(&self, time: f64) -> Option<(HitRecord<'_>, f64)> {
+        match self {
+            Hittable::Quad(obj) => Some(obj.sample_point(time)),
+            Hittable::Sphere(obj) => Some(obj.sample_point(time)),
+            Hittable::PrefabScene(obj) => obj.sample_point(time),
+            Hittable::Rotate(obj) => obj.sample_point(time),
+            Hittable::Translate(obj) => obj.sample_point(time),
+            Hittable::BVH(_) | Hittable::ConstantMedium(_) => None,
+        }
+    }
 }
@@ -4,8 +4,9 @@ use crate::{
     aabb::AABB,
     material::Material,
     ray::Ray,
+    sampler::get_2d,
     scene::Scene,
-    util::{random_f64, Interval},
+    util::Interval,
 };
 
 use super::HitRecord;
@@ -21,6 +22,7 @@ pub struct Quad {
     pub material: Material,
     pub aabb: AABB,
     pub area: f64,
+    pub object_id: usize, // ID reported by its hits, offset by the scenes it is added to
 }
 
 impl Quad {
@@ -44,6 +46,7 @@ impl Quad {
             d,
             w,
             area: n.norm(),
+            object_id: 0,
         }
     }
 
@@ -76,6 +79,8 @@ impl Quad {
                     uv,
                     front_face: true,
                     trace: false,
+                    time: ray.time,
+                    object_id: self.object_id,
                 };
                 rec.set_face_normal(ray, &self.normal);
                 Some(rec)
@@ -111,9 +116,27 @@ impl Quad {
         }
     }
     pub fn random(&self, origin: &Vector3<f64>) -> Vector3<f64> {
-        let p = self.q + (random_f64() * self.u) + (random_f64() * self.v);
+        let u = get_2d();
+        let p = self.q + (u.x * self.u) + (u.y * self.v);
         p - origin
     }
+
+    /// Uniformly distributed point on the quad at `time` and its area density.
+    pub fn sample_point(&self, time: f64) -> (HitRecord<'_>, f64) {
+        let u = get_2d();
+        let rec = HitRecord {
+            t: 0.0,
+            p: self.q + u.x * self.u + u.y * self.v,
+            normal: self.normal,
+            front_face: true,
+            uv: u,
+            material: &self.material,
+            trace: false,
+            time,
+            object_id: self.object_id,
+        };
+        (rec, 1.0 / self.area)
+    }
 }
 
 pub fn box_scene(a: Vector3<f64>, b: Vector3<f64>, mat: Material) -> Scene {
@@ -6,11 +6,13 @@ use std::{
 use nalgebra::{Vector2, Vector3};
 
 use crate::{
-    aabb::AABB,
+    aabb::{AABB, AABB_EMPTY},
+    keyframe::Keyframes,
     material::Material,
     onb::Onb,
     ray::Ray,
-    util::{random_f64, Interval},
+    sampler::get_2d,
+    util::Interval,
 };
 
 use super::HitRecord;
@@ -20,7 +22,9 @@ pub struct Sphere {
     pub radius: f64,
     pub material: Material,
     pub motion: Option<Vector3<f64>>,
+    pub animated_center: Keyframes<Vector3<f64>>, // Center keyed over ray time, overrides motion
     pub bbox: AABB,
+    pub object_id: usize, // ID reported by its hits, offset by the scenes it is added to
 }
 
 impl Sphere {
@@ -30,6 +34,8 @@ impl Sphere {
             radius,
             material,
             motion: None,
+            animated_center: Keyframes::default(),
+            object_id: 0,
             bbox: AABB::new(
                 center - Vector3::new(radius, radius, radius),
                 center + Vector3::new(radius, radius, radius),
@@ -42,17 +48,27 @@ impl Sphere {
         radius: f64,
         material: Material,
     ) -> Self {
+        Self::new_animated(
+            Keyframes::new().key(0.0, center).key(1.0, motion_center),
+            radius,
+            material,
+        )
+    }
+    /// Moves the sphere along the keyframed centers, evaluated at the time of each ray.
+    pub fn new_animated(center: Keyframes<Vector3<f64>>, radius: f64, material: Material) -> Self {
+        // Centers are interpolated linearly, so the boxes at the keys enclose all others.
         let rvec = Vector3::new(radius, radius, radius);
-        let box1 = AABB::new(center - rvec, center + rvec);
-        let box2 = AABB::new(motion_center - rvec, motion_center + rvec);
-        let bbox = AABB::merge(&box1, &box2);
-
+        let bbox = center.keys().iter().fold(AABB_EMPTY, |bbox, (_, c)| {
+            AABB::merge(&bbox, &AABB::new(c - rvec, c + rvec))
+        });
         Self {
-            center,
+            center: center.sample(0.0).unwrap_or_default(),
             radius,
             material,
             motion: None,
+            animated_center: center,
             bbox,
+            object_id: 0,
         }
     }
     fn get_sphere_uv(p: &Vector3<f64>) -> Vector2<f64> {
@@ -63,10 +79,7 @@ impl Sphere {
         Vector2::new(u, v)
     }
     pub fn hit(&self, ray: &crate::ray::Ray, interval: &Interval) -> Option<HitRecord> {
-        let center = match self.motion {
-            Some(_) => self.sphere_center(ray.time),
-            None => self.center,
-        };
+        let center = self.sphere_center(ray.time);
         let oc = center - ray.origin;
         let a = ray.direction.norm_squared();
         let h = ray.direction.dot(&oc);
@@ -86,7 +99,7 @@ impl Sphere {
         }
         let t = root;
         let p = ray.at(t);
-        let outward_normal = (p - self.center).normalize();
+        let outward_normal = (p - center).normalize();
         let uv = Self::get_sphere_uv(&outward_normal);
 
         let mut hit_record = HitRecord {
@@ -97,6 +110,8 @@ impl Sphere {
             material: &self.material,
             uv,
             trace: true,
+            time: ray.time,
+            object_id: self.object_id,
         };
         hit_record.set_face_normal(ray, &outward_normal);
 
@@ -104,7 +119,10 @@ impl Sphere {
     }
 
     pub fn sphere_center(&self, time: f64) -> Vector3<f64> {
-        self.center + self.motion.unwrap_or(Vector3::zeros()) * time
+        match self.animated_center.sample(time) {
+            Some(center) => center,
+            None => self.center + self.motion.unwrap_or(Vector3::zeros()) * time,
+        }
     }
 
     pub fn pdf_value(&self, origin: &Vector3<f64>, direction: &Vector3<f64>) -> f64 {
@@ -132,9 +150,31 @@ impl Sphere {
         uvw.local_v(Self::random_to_sphere(self.radius, distance_squared))
     }
 
+    /// Uniformly distributed point on the sphere at `time` and its area density.
+    pub fn sample_point(&self, time: f64) -> (HitRecord<'_>, f64) {
+        let u = get_2d();
+        let z = 1.0 - 2.0 * u.x;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.y;
+        let normal = Vector3::new(r * phi.cos(), r * phi.sin(), z);
+        let center = self.sphere_center(time);
+        let rec = HitRecord {
+            t: 0.0,
+            p: center + self.radius * normal,
+            normal,
+            front_face: true,
+            uv: Self::get_sphere_uv(&normal),
+            material: &self.material,
+            trace: true,
+            time,
+            object_id: self.object_id,
+        };
+        (rec, 1.0 / (4.0 * PI * self.radius * self.radius))
+    }
+
     fn random_to_sphere(radius: f64, distance_squared: f64) -> Vector3<f64> {
-        let r1 = random_f64();
-        let r2 = random_f64();
+        let u = get_2d();
+        let (r1, r2) = (u.x, u.y);
         let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
 
         let phi = 2.0 * PI * r1;
@@ -1,12 +1,18 @@
 use nalgebra::Vector3;
 
-use crate::{aabb::AABB, ray::Ray, util::Interval};
+use crate::{
+    aabb::{AABB, AABB_EMPTY},
+    keyframe::Keyframes,
+    ray::Ray,
+    util::Interval,
+};
 
 use super::{HitRecord, Hittable};
 #[derive(Debug, Clone)]
 
 pub struct Translate {
     pub offset: Vector3<f64>,
+    pub motion: Keyframes<Vector3<f64>>, // Offset keyed over ray time, overrides offset
     pub object: Box<Hittable>,
     pub bbox: AABB,
 }
@@ -16,22 +22,44 @@ impl Translate {
         let bbox = object.bbox().clone().add_vec(offset);
         Self {
             offset,
+            motion: Keyframes::default(),
+            object: Box::new(object),
+            bbox,
+        }
+    }
+    /// Moves `object` along the keyframed offsets, evaluated at the time of each ray.
+    pub fn new_animated(object: Hittable, motion: Keyframes<Vector3<f64>>) -> Self {
+        // Offsets are interpolated linearly, so the boxes at the keys enclose all others.
+        let bbox = motion.keys().iter().fold(AABB_EMPTY, |bbox, (_, offset)| {
+            AABB::merge(&bbox, &object.bbox().add_vec(*offset))
+        });
+        Self {
+            offset: motion.sample(0.0).unwrap_or_default(),
+            motion,
             object: Box::new(object),
             bbox,
         }
     }
     pub fn hit(&self, ray: &crate::ray::Ray, interval: &Interval) -> Option<HitRecord> {
-        let offset_r = Ray::new_with_time(ray.origin - self.offset, ray.direction, ray.time);
+        let offset = self.motion.sample(ray.time).unwrap_or(self.offset);
+        let offset_r = Ray::new_with_time(ray.origin - offset, ray.direction, ray.time);
 
         match self.object.hit(&offset_r, interval) {
             Some(mut rec) => {
-                rec.p += self.offset;
+                rec.p += offset;
                 Some(rec)
             }
             None => None,
         }
     }
 
+    pub fn sample_point(&self, time: f64) -> Option<(HitRecord<'_>, f64)> {
+        let offset = self.motion.sample(time).unwrap_or(self.offset);
+        let (mut rec, pdf) = self.object.sample_point(time)?;
+        rec.p += offset;
+        Some((rec, pdf))
+    }
+
     pub fn bbox(&self) -> &AABB {
         &self.bbox
     }
@@ -41,6 +69,7 @@ impl Translate {
 pub struct RotateY {
     pub sin_theta: f64,
     pub cos_theta: f64,
+    pub angle: Keyframes<f64>, // Degrees keyed over ray time, overrides sin_theta and cos_theta
     pub bbox: AABB,
     pub object: Box<Hittable>,
 }
@@ -76,33 +105,60 @@ impl RotateY {
         Self {
             sin_theta,
             cos_theta,
+            angle: Keyframes::default(),
+            bbox,
+            object: Box::new(object),
+        }
+    }
+
+    /// Rotates `object` by the keyframed angles, evaluated at the time of each ray.
+    pub fn new_animated(object: Hittable, angle: Keyframes<f64>) -> Self {
+        // Bound every possible rotation by the cylinder swept around the y axis.
+        let bbox = object.bbox();
+        let radius = [bbox.x.min, bbox.x.max]
+            .iter()
+            .flat_map(|x| [bbox.z.min, bbox.z.max].map(|z| x.hypot(z)))
+            .fold(0.0, f64::max);
+        let bbox = AABB::new(
+            Vector3::new(-radius, bbox.y.min, -radius),
+            Vector3::new(radius, bbox.y.max, radius),
+        );
+        let radians = angle.sample(0.0).unwrap_or_default().to_radians();
+        Self {
+            sin_theta: radians.sin(),
+            cos_theta: radians.cos(),
+            angle,
             bbox,
             object: Box::new(object),
         }
     }
 
     pub fn hit(&self, ray: &crate::ray::Ray, interval: &Interval) -> Option<HitRecord> {
+        let (sin_theta, cos_theta) = match self.angle.sample(ray.time) {
+            Some(angle) => angle.to_radians().sin_cos(),
+            None => (self.sin_theta, self.cos_theta),
+        };
         let mut origin = ray.origin;
         let mut direction = ray.direction;
 
-        origin.x = self.cos_theta * ray.origin.x - self.sin_theta * ray.origin.z;
-        origin.z = self.sin_theta * ray.origin.x + self.cos_theta * ray.origin.z;
+        origin.x = cos_theta * ray.origin.x - sin_theta * ray.origin.z;
+        origin.z = sin_theta * ray.origin.x + cos_theta * ray.origin.z;
 
-        direction.x = self.cos_theta * ray.direction.x - self.sin_theta * ray.direction.z;
-        direction.z = self.sin_theta * ray.direction.x + self.cos_theta * ray.direction.z;
+        direction.x = cos_theta * ray.direction.x - sin_theta * ray.direction.z;
+        direction.z = sin_theta * ray.direction.x + cos_theta * ray.direction.z;
 
         let rotated_r = Ray::new_with_time(origin, direction, ray.time);
 
         match self.object.hit(&rotated_r, interval) {
             Some(mut rec) => {
                 let mut p = rec.p;
-                p.x = self.cos_theta * rec.p.x + self.sin_theta * rec.p.z;
-                p.z = -self.sin_theta * rec.p.x + self.cos_theta * rec.p.z;
+                p.x = cos_theta * rec.p.x + sin_theta * rec.p.z;
+                p.z = -sin_theta * rec.p.x + cos_theta * rec.p.z;
 
                 // 将法线从对象空间变换到世界空间
                 let mut normal = rec.normal;
-                normal.x = self.cos_theta * rec.normal.x + self.sin_theta * rec.normal.z;
-                normal.z = -self.sin_theta * rec.normal.z + self.cos_theta * rec.normal.z;
+                normal.x = cos_theta * rec.normal.x + sin_theta * rec.normal.z;
+                normal.z = -sin_theta * rec.normal.z + cos_theta * rec.normal.z;
 
                 rec.p = p;
                 rec.normal = normal;
@@ -111,6 +167,24 @@ impl RotateY {
             None => None,
         }
     }
+
+    pub fn sample_point(&self, time: f64) -> Option<(HitRecord<'_>, f64)> {
+        let (sin_theta, cos_theta) = match self.angle.sample(time) {
+            Some(angle) => angle.to_radians().sin_cos(),
+            None => (self.sin_theta, self.cos_theta),
+        };
+        let (mut rec, pdf) = self.object.sample_point(time)?;
+        let to_world = |v: Vector3<f64>| {
+            Vector3::new(
+                cos_theta * v.x + sin_theta * v.z,
+                v.y,
+                -sin_theta * v.x + cos_theta * v.z,
+            )
+        };
+        rec.p = to_world(rec.p);
+        rec.normal = to_world(rec.normal);
+        Some((rec, pdf))
+    }
     fn bbox(&self) -> &AABB {
         &self.bbox
     }
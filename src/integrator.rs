@@ -0,0 +1,278 @@
+use nalgebra::Vector3;
+
+use crate::{
+    aov::{AovSample, Radiance},
+    bdpt::bdpt,
+    camera::Camera,
+    film::LightSplat,
+    hit::{HitRecord, Hittable},
+    mlt::Mutation,
+    onb::Onb,
+    ray::Ray,
+    sampler::get_2d,
+    util::{cosine_direction, random_f64, Interval},
+};
+
+/// Light transport algorithm turning camera rays into radiance.
+///
+/// The debug integrators return their value as emission and black where the camera
+/// ray misses.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Integrator {
+    /// Unidirectional path tracing up to the camera's `max_depth` bounces.
+    #[default]
+    Path,
+    /// Bidirectional path tracing, connecting every vertex of a camera path to every
+    /// vertex of a light path, up to the camera's `max_depth` bounces. Light paths
+    /// are also connected to the lens and splatted onto the film, for perspective
+    /// cameras with a pinhole or disk aperture only.
+    Bidirectional,
+    /// Stochastic progressive photon mapping. Every iteration records the first
+    /// non-specular camera hit of each pixel, then emits `photons_per_pass` photons
+    /// from the lights and gathers those landing near the hits. The gather radius
+    /// starts at `initial_radius` and shrinks per pixel as photons arrive, which
+    /// resolves caustics behind specular surfaces. Runs `samples_per_pixel`
+    /// iterations through `Camera::render`, single rays are path traced.
+    PhotonMapping {
+        photons_per_pass: usize,
+        initial_radius: f64,
+    },
+    /// Primary sample space Metropolis light transport, mutating the random numbers
+    /// that drive the path tracer so bright paths found through small openings are
+    /// explored locally. `bootstrap_samples` independent paths estimate the image
+    /// brightness and seed `chains` Markov chains, which run `samples_per_pixel`
+    /// mutations per pixel in total through `Camera::render`, single rays are path
+    /// traced.
+    Metropolis {
+        bootstrap_samples: usize,
+        chains: usize,
+        mutation: Mutation,
+    },
+    /// Light reaching the camera after at most one bounce.
+    Direct,
+    /// Fraction of the hemisphere above the first hit that is unoccluded within
+    /// `distance`.
+    AmbientOcclusion { distance: f64 },
+    /// Shading normal, mapped from [-1,1] to [0,1].
+    Normal,
+    /// Texture coordinates in the red and green channels.
+    Uv,
+    /// `HitRecord::t` of the first hit. Planar camera rays reach the focus plane at
+    /// t=1, panoramic ones have unit length so t is the distance.
+    Depth,
+    /// Green where the camera ray hits the front face, red where it hits the back.
+    FrontFace,
+}
+
+/// Weighting of the light and material samples combined by multiple importance
+/// sampling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MisHeuristic {
+    Balance,
+    /// Balance heuristic with squared densities, favours the locally better strategy.
+    #[default]
+    Power,
+}
+
+impl MisHeuristic {
+    /// Weight of a sample drawn with density `pdf` when the other strategy would
+    /// have drawn it with `other_pdf`.
+    pub fn weight(&self, pdf: f64, other_pdf: f64) -> f64 {
+        let (a, b) = match self {
+            MisHeuristic::Balance => (pdf, other_pdf),
+            MisHeuristic::Power => (pdf * pdf, other_pdf * other_pdf),
+        };
+        if a + b > 0.0 {
+            a / (a + b)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Light emitted back along `ray` by the first surface it hits, or `background`
+/// on a miss.
+pub(crate) fn emitted_along(ray: &Ray, world: &Hittable, background: Vector3<f64>) -> Vector3<f64> {
+    match world.hit(ray, &Interval::new(0.001, f64::INFINITY)) {
+        Some(rec) => rec.material.emitted(&rec.uv, &rec.p, &rec),
+        None => background,
+    }
+}
+
+/// Fills `aov` with the first hit `rec` of the camera ray `r`.
+pub(crate) fn record_aov(aov: &mut AovSample, r: &Ray, rec: &HitRecord) {
+    *aov = AovSample {
+        hit: true,
+        albedo: rec.material.albedo(rec),
+        normal: rec.normal,
+        position: rec.p,
+        depth: rec.t * r.direction.norm(),
+        object_id: rec.object_id,
+    };
+}
+
+impl Integrator {
+    /// Radiance arriving along the camera ray `r`, split by the bounce the light was
+    /// emitted at. `aov` is filled in at the first hit when given. Light that reaches
+    /// the camera through other pixels is pushed to `splats`.
+    pub fn radiance(
+        &self,
+        camera: &Camera,
+        r: &Ray,
+        world: &Hittable,
+        lights: &Hittable,
+        aov: Option<&mut AovSample>,
+        splats: &mut Vec<LightSplat>,
+    ) -> Radiance {
+        match *self {
+            Integrator::Path | Integrator::PhotonMapping { .. } | Integrator::Metropolis { .. } => {
+                path(camera, camera.max_depth, r, world, lights, aov)
+            }
+            Integrator::Bidirectional => bdpt(camera, r, world, lights, aov, splats),
+            Integrator::Direct => path(camera, camera.max_depth.min(2), r, world, lights, aov),
+            _ => {
+                let Some(rec) = world.hit(r, &Interval::new(0.001, f64::INFINITY)) else {
+                    return Radiance::default();
+                };
+                if let Some(aov) = aov {
+                    record_aov(aov, r, &rec);
+                }
+                Radiance {
+                    emission: self.debug_value(r, &rec, world),
+                    ..Default::default()
+                }
+            }
+        }
+    }
+
+    /// Value of a debug integrator at the first hit `rec`.
+    fn debug_value(&self, r: &Ray, rec: &HitRecord, world: &Hittable) -> Vector3<f64> {
+        match *self {
+            Integrator::AmbientOcclusion { distance } => {
+                let direction = Onb::new_from_w(rec.normal).local_v(cosine_direction(&get_2d()));
+                let occlusion_ray = Ray::new_with_time(rec.p, direction.normalize(), r.time);
+                match world.hit(&occlusion_ray, &Interval::new(0.001, distance)) {
+                    Some(_) => Vector3::zeros(),
+                    None => Vector3::repeat(1.0),
+                }
+            }
+            Integrator::Normal => 0.5 * (rec.normal + Vector3::repeat(1.0)),
+            Integrator::Uv => Vector3::new(rec.uv.x, rec.uv.y, 0.0),
+            Integrator::Depth => Vector3::repeat(rec.t),
+            Integrator::FrontFace => {
+                if rec.front_face {
+                    Vector3::new(0.0, 1.0, 0.0)
+                } else {
+                    Vector3::new(1.0, 0.0, 0.0)
+                }
+            }
+            Integrator::Path
+            | Integrator::Bidirectional
+            | Integrator::PhotonMapping { .. }
+            | Integrator::Metropolis { .. }
+            | Integrator::Direct => Vector3::zeros(),
+        }
+    }
+}
+
+/// Traces the path starting with `r` for up to `max_depth` bounces.
+///
+/// Every non-specular hit sends a shadow ray to a point sampled on `lights` and
+/// continues with a material sample. Both find emitters, so their contributions are
+/// weighted by the camera's `mis_heuristic`. Specular bounces cannot be light
+/// sampled and keep the full emission they hit.
+fn path(
+    camera: &Camera,
+    max_depth: usize,
+    r: &Ray,
+    world: &Hittable,
+    lights: &Hittable,
+    mut aov: Option<&mut AovSample>,
+) -> Radiance {
+    let mut radiance = Radiance::default();
+    let mut ray = r.clone();
+    // Product of the scattering weights along the path so far.
+    let mut throughput = Vector3::repeat(1.0);
+    // Density the material sampled `ray` with, None for camera rays and after
+    // specular bounces.
+    let mut material_pdf = None;
+
+    // 如果我们超过了光线反弹限制，就不再收集光线。
+    for bounce in 0..max_depth {
+        // 如果光线没有击中了世界中的任何东西，则返回背景颜色。
+        let hit = world.hit(&ray, &Interval::new(0.001, f64::INFINITY));
+        let color_from_emission = match &hit {
+            Some(rec) => rec.material.emitted(&rec.uv, &rec.p, rec),
+            None => camera.background,
+        };
+        let mis_weight = match material_pdf {
+            Some(pdf) => camera
+                .mis_heuristic
+                .weight(pdf, lights.pdf_value(&ray.origin, &ray.direction)),
+            None => 1.0,
+        };
+        radiance.add(
+            bounce,
+            mis_weight * throughput.component_mul(&color_from_emission),
+        );
+        let Some(rec) = hit else {
+            break;
+        };
+        let mat = rec.material;
+
+        if let Some(aov) = aov.take() {
+            record_aov(aov, &ray, &rec);
+        }
+
+        let Some(srec) = mat.scatter(&ray, &rec) else {
+            break;
+        };
+        let (scattered, weight) = if srec.skip_pdf {
+            material_pdf = None;
+            (srec.skip_pdf_ray, srec.attenuation)
+        } else {
+            // Next event estimation, the light found by the shadow ray arrives one
+            // bounce later.
+            if bounce + 1 < max_depth {
+                let direction = lights.random(&rec.p);
+                let light_pdf = lights.pdf_value(&rec.p, &direction);
+                let shadow_ray = Ray::new_with_time(rec.p, direction, ray.time);
+                let scattering_pdf = mat.scattering_pdf(&ray, &shadow_ray, &rec);
+                if light_pdf > 0.0 && scattering_pdf > 0.0 {
+                    let light = emitted_along(&shadow_ray, world, camera.background);
+                    let mis_weight = camera
+                        .mis_heuristic
+                        .weight(light_pdf, srec.pdf.value(&direction));
+                    let weight = srec.attenuation * mis_weight * scattering_pdf / light_pdf;
+                    radiance.add(
+                        bounce + 1,
+                        throughput.component_mul(&weight).component_mul(&light),
+                    );
+                }
+            }
+
+            let direction = srec.pdf.generate();
+            let pdf = srec.pdf.value(&direction);
+            if pdf <= 0.0 {
+                break;
+            }
+            material_pdf = Some(pdf);
+            let scattered = Ray::new_with_time(rec.p, direction, ray.time);
+            let scattering_pdf = mat.scattering_pdf(&ray, &scattered, &rec);
+            (scattered, srec.attenuation * scattering_pdf / pdf)
+        };
+        throughput = throughput.component_mul(&weight);
+
+        // Past `roulette_depth` bounces paths survive with a probability following
+        // their throughput, survivors are weighted up to keep the estimate unbiased.
+        if camera.roulette_depth > 0 && bounce >= camera.roulette_depth {
+            let survival = throughput.max().min(1.0);
+            if random_f64() >= survival {
+                break;
+            }
+            throughput /= survival;
+        }
+        ray = scattered;
+    }
+    radiance
+}
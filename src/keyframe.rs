@@ -0,0 +1,73 @@
+use nalgebra::Vector3;
+
+/// Values that can be blended between two keyframes.
+pub trait Interpolate: Clone {
+    /// Blends from `self` at t=0 to `other` at t=1.
+    fn lerp(&self, other: &Self, t: f64) -> Self;
+}
+
+impl Interpolate for f64 {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for Vector3<f64> {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        self + (other - self) * t
+    }
+}
+
+/// Values keyed at points in time, linearly interpolated in between and held
+/// constant before the first and after the last key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keyframes<T> {
+    keys: Vec<(f64, T)>, // Time and value, sorted by time
+}
+
+impl<T> Default for Keyframes<T> {
+    fn default() -> Self {
+        Self { keys: Vec::new() }
+    }
+}
+
+impl<T: Interpolate> Keyframes<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a key, replacing any existing key at the same time.
+    pub fn insert(&mut self, time: f64, value: T) {
+        match self.keys.binary_search_by(|(t, _)| t.total_cmp(&time)) {
+            Ok(idx) => self.keys[idx].1 = value,
+            Err(idx) => self.keys.insert(idx, (time, value)),
+        }
+    }
+
+    /// Builder style `insert`.
+    pub fn key(mut self, time: f64, value: T) -> Self {
+        self.insert(time, value);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn keys(&self) -> &[(f64, T)] {
+        &self.keys
+    }
+
+    /// Value at `time`, None without any keys.
+    pub fn sample(&self, time: f64) -> Option<T> {
+        let idx = self.keys.partition_point(|(t, _)| *t <= time);
+        match (
+            idx.checked_sub(1).map(|i| &self.keys[i]),
+            self.keys.get(idx),
+        ) {
+            (Some((t0, v0)), Some((t1, v1))) => Some(v0.lerp(v1, (time - t0) / (t1 - t0))),
+            (Some((_, v)), None) | (None, Some((_, v))) => Some(v.clone()),
+            (None, None) => None,
+        }
+    }
+}
@@ -3,10 +3,29 @@ pub mod hit;
 pub mod scene;
 pub mod util;
 pub mod camera;
+pub mod checkpoint;
+pub mod film;
 pub mod material;
 pub mod bvh;
 pub mod texture;
 pub mod noise;
 pub mod aabb;
 pub mod onb;
-pub mod pdf;
\ No newline at end of file
+pub mod pdf;
+pub mod output;
+pub mod tile;
+pub mod aov;
+pub mod tonemap;
+pub mod filter;
+pub mod rng;
+pub mod sampler;
+pub mod projection;
+pub mod aperture;
+pub mod keyframe;
+pub mod shutter;
+pub mod animation;
+pub mod stereo;
+pub mod integrator;
+pub mod bdpt;
+pub mod sppm;
+pub mod mlt;
\ No newline at end of file
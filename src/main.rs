@@ -110,7 +110,9 @@ fn cornell_box() {
     cam.render(
         Hittable::PrefabScene(world),
         Hittable::PrefabScene(lights),
-    );
+    )
+    .save("image.png")
+    .expect("Failed to save image");
 }
 
 fn main() {
@@ -4,6 +4,7 @@ use nalgebra::{Vector2, Vector3};
 
 use crate::{
     hit::{self, HitRecord},
+    keyframe::Keyframes,
     pdf::{CosinePdf, NonePDF, PDF},
     ray::Ray,
     texture::{SolidColor, Texture},
@@ -55,6 +56,15 @@ impl Material {
             Material::DiffuseLight(light) => light.emitted(uv, p, rec),
         }
     }
+    /// Reflectance at the hit point, emitters report their emitted color.
+    pub fn albedo(&self, rec: &HitRecord) -> Vector3<f64> {
+        match self {
+            Material::Diffuse(lambert) => lambert.albedo(rec),
+            Material::Metal(metal) => metal.albedo(rec.time),
+            Material::Dielectric(_) => Vector3::new(1.0, 1.0, 1.0),
+            Material::DiffuseLight(light) => light.emit(rec),
+        }
+    }
     pub fn scattering_pdf(&self, ray: &Ray, scattered: &Ray, rec: &HitRecord) -> f64 {
         match self {
             Material::Diffuse(lambert) => lambert.scattering_pdf(ray, scattered, rec),
@@ -64,26 +74,57 @@ impl Material {
             _ => 0.0,
         }
     }
+    /// Scattering of light arriving from `direction` into the reversed `ray`, without
+    /// the cosine term. Zero for specular materials, which cannot be evaluated for a
+    /// given pair of directions.
+    pub fn bsdf(&self, ray: &Ray, rec: &HitRecord, direction: &Vector3<f64>) -> Vector3<f64> {
+        let cos = rec.normal.dot(&direction.normalize()).abs();
+        match self.scatter(ray, rec) {
+            Some(srec) if !srec.skip_pdf && cos > 0.0 => {
+                let scattered = Ray::new_with_time(rec.p, *direction, ray.time);
+                srec.attenuation * self.scattering_pdf(ray, &scattered, rec) / cos
+            }
+            _ => Vector3::zeros(),
+        }
+    }
 }
 #[derive(Debug, Clone)]
 pub struct Lambertian {
     albedo: Box<Texture>,
+    pub animated_albedo: Keyframes<Vector3<f64>>, // Albedo keyed over ray time, overrides albedo
 }
 
 impl Lambertian {
     pub fn new_with_color(albedo: Vector3<f64>) -> Self {
         Self {
             albedo: Box::new(Texture::Color(SolidColor::new(albedo))),
+            animated_albedo: Keyframes::default(),
         }
     }
     pub fn new(tex: Texture) -> Self {
         Self {
             albedo: Box::new(tex),
+            animated_albedo: Keyframes::default(),
         }
     }
+    /// Diffuse surface whose albedo follows the keyframes, evaluated at the time of
+    /// each ray.
+    pub fn new_animated(albedo: Keyframes<Vector3<f64>>) -> Self {
+        Self {
+            albedo: Box::new(Texture::Color(SolidColor::new(
+                albedo.sample(0.0).unwrap_or_default(),
+            ))),
+            animated_albedo: albedo,
+        }
+    }
+    fn albedo(&self, rec: &HitRecord) -> Vector3<f64> {
+        self.animated_albedo
+            .sample(rec.time)
+            .unwrap_or_else(|| self.albedo.value(&rec.uv, &rec.p))
+    }
     pub fn scatter(&self, _ray: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let mut srec = ScatterRecord::default();
-        srec.attenuation = self.albedo.value(&rec.uv, &rec.p);
+        srec.attenuation = self.albedo(rec);
         srec.pdf = PDF::Cosine(CosinePdf::new(rec.normal));
         srec.skip_pdf = false;
         Some(srec)
@@ -101,26 +142,44 @@ impl Lambertian {
         }
     }
 }
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Metal {
     albedo: Vector3<f64>,
     fuzz: f64,
+    pub animated_albedo: Keyframes<Vector3<f64>>, // Albedo keyed over ray time, overrides albedo
+    pub animated_fuzz: Keyframes<f64>,            // Fuzz keyed over ray time, overrides fuzz
 }
 
 impl Metal {
     pub fn new(albedo: Vector3<f64>, fuzz: f64) -> Self {
-        Self { albedo, fuzz }
+        Self {
+            albedo,
+            fuzz,
+            animated_albedo: Keyframes::default(),
+            animated_fuzz: Keyframes::default(),
+        }
+    }
+    /// Metal whose albedo and fuzz follow the keyframes, evaluated at the time of each
+    /// ray.
+    pub fn new_animated(albedo: Keyframes<Vector3<f64>>, fuzz: Keyframes<f64>) -> Self {
+        Self {
+            albedo: albedo.sample(0.0).unwrap_or_default(),
+            fuzz: fuzz.sample(0.0).unwrap_or_default(),
+            animated_albedo: albedo,
+            animated_fuzz: fuzz,
+        }
+    }
+    fn albedo(&self, time: f64) -> Vector3<f64> {
+        self.animated_albedo.sample(time).unwrap_or(self.albedo)
     }
     pub fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let mut srec = ScatterRecord::default();
-        srec.attenuation = self.albedo;
+        srec.attenuation = self.albedo(rec.time);
         srec.skip_pdf = true;
         let reflected = reflect(&ray.direction.normalize(), &rec.normal);
-        srec.skip_pdf_ray = Ray::new_with_time(
-            rec.p,
-            reflected + self.fuzz * random_in_unit_sphere(),
-            ray.time,
-        );
+        let fuzz = self.animated_fuzz.sample(rec.time).unwrap_or(self.fuzz);
+        srec.skip_pdf_ray =
+            Ray::new_with_time(rec.p, reflected + fuzz * random_in_unit_sphere(), ray.time);
         Some(srec)
     }
     pub fn emitted(&self, _uv: &Vector2<f64>, _p: &Vector3<f64>, _rec: &HitRecord) -> Vector3<f64> {
@@ -128,24 +187,40 @@ impl Metal {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Dielectric {
     refraction_index: f64,
+    pub animated_refraction_index: Keyframes<f64>, // Keyed over ray time, overrides the index
 }
 
 impl Dielectric {
     pub fn new(refraction_index: f64) -> Self {
-        Self { refraction_index }
+        Self {
+            refraction_index,
+            animated_refraction_index: Keyframes::default(),
+        }
+    }
+    /// Glass whose refraction index follows the keyframes, evaluated at the time of
+    /// each ray.
+    pub fn new_animated(refraction_index: Keyframes<f64>) -> Self {
+        Self {
+            refraction_index: refraction_index.sample(0.0).unwrap_or(1.0),
+            animated_refraction_index: refraction_index,
+        }
     }
 
     pub fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let mut srec = ScatterRecord::default();
         srec.attenuation = Vector3::new(1.0, 1.0, 1.0);
         srec.skip_pdf = true;
+        let refraction_index = self
+            .animated_refraction_index
+            .sample(rec.time)
+            .unwrap_or(self.refraction_index);
         let refraction_ratio = if rec.front_face {
-            1.0 / self.refraction_index
+            1.0 / refraction_index
         } else {
-            self.refraction_index
+            refraction_index
         };
 
         let unit_direction = ray.direction.normalize();
@@ -172,23 +247,42 @@ impl Dielectric {
 
 pub struct DiffuseLight {
     pub emit: Box<Texture>,
+    pub animated_emit: Keyframes<Vector3<f64>>, // Emitted color keyed over ray time, overrides emit
 }
 
 impl DiffuseLight {
     pub fn new_with_color(emit: Vector3<f64>) -> Self {
         Self {
             emit: Box::new(Texture::Color(SolidColor::new(emit))),
+            animated_emit: Keyframes::default(),
         }
     }
     pub fn new(tex: Texture) -> Self {
         Self {
             emit: Box::new(tex),
+            animated_emit: Keyframes::default(),
+        }
+    }
+    /// Light whose color follows the keyframes, evaluated at the time of each ray.
+    pub fn new_animated(emit: Keyframes<Vector3<f64>>) -> Self {
+        Self {
+            emit: Box::new(Texture::Color(SolidColor::new(
+                emit.sample(0.0).unwrap_or_default(),
+            ))),
+            animated_emit: emit,
         }
     }
+    fn emit(&self, rec: &HitRecord) -> Vector3<f64> {
+        self.animated_emit
+            .sample(rec.time)
+            .unwrap_or_else(|| self.emit.value(&rec.uv, &rec.p))
+    }
 
     pub fn emitted(&self, uv: &Vector2<f64>, p: &Vector3<f64>, rec: &HitRecord) -> Vector3<f64> {
         if rec.front_face {
-            self.emit.value(uv, p)
+            self.animated_emit
+                .sample(rec.time)
+                .unwrap_or_else(|| self.emit.value(uv, p))
         } else {
             Vector3::default()
         }
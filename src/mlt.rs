@@ -0,0 +1,369 @@
+use std::{cell::RefCell, f64::consts::PI};
+
+use nalgebra::Vector3;
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    camera::Camera,
+    film::{Film, LightSplat},
+    hit::Hittable,
+    integrator::Integrator,
+    rng::{hash, Rng},
+    sampler::get_2d,
+    tile::Tile,
+    util::{luminance, seed_thread_rng},
+};
+
+/// Mutations of all chains between two film updates, bounds the memory held by
+/// their splats.
+const MUTATIONS_PER_ROUND: usize = 1 << 20;
+
+/// How a Metropolis chain proposes its next state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mutation {
+    /// Probability of replacing all random numbers at once, which lets chains
+    /// escape to unrelated paths.
+    pub large_step_probability: f64,
+    /// Standard deviation of the Gaussian step of each random number otherwise.
+    pub sigma: f64,
+}
+
+impl Default for Mutation {
+    fn default() -> Self {
+        Self {
+            large_step_probability: 0.3,
+            sigma: 0.01,
+        }
+    }
+}
+
+/// One dimension of the primary sample space.
+#[derive(Debug, Clone, Copy, Default)]
+struct PrimarySample {
+    value: f64,
+    last_modified: u64, // Iteration the value was last mutated in
+    backup: f64,
+    modify_backup: u64,
+}
+
+/// State of a Markov chain over the primary sample space, the unit hypercube of
+/// random numbers consumed by `get_1d` and `get_2d` while tracing a path
+/// (Kelemen et al. 2002, "A Simple and Robust Mutation Strategy for the Metropolis
+/// Light Transport Algorithm").
+///
+/// Dimensions are mutated lazily when they are requested, so paths may consume any
+/// number of them. The independent random stream is part of the state too: its
+/// seed only changes with large steps.
+#[derive(Debug, Clone)]
+struct PrimarySamples {
+    rng: Rng,
+    mutation: Mutation,
+    values: Vec<PrimarySample>,
+    iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+    dimension: usize,
+    stream: u64,
+    stream_backup: u64,
+}
+
+thread_local! {
+    static CHAIN: RefCell<Option<PrimarySamples>> = const { RefCell::new(None) };
+}
+
+impl PrimarySamples {
+    /// Starts a chain whose first state is a large step drawn from `seed`.
+    fn new(seed: u64, mutation: Mutation) -> Self {
+        let mut rng = Rng::new(seed, 0);
+        let stream = hash(&[rng.next_u32() as u64, rng.next_u32() as u64]);
+        Self {
+            rng,
+            mutation,
+            values: vec![],
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            dimension: 0,
+            stream,
+            stream_backup: stream,
+        }
+    }
+
+    /// Proposes the next state, the dimensions follow as they are requested.
+    fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.next_f64() < self.mutation.large_step_probability;
+        if self.large_step {
+            self.stream_backup = self.stream;
+            self.stream = hash(&[self.rng.next_u32() as u64, self.rng.next_u32() as u64]);
+        }
+    }
+
+    /// Restarts the dimensions and the independent random stream of the current
+    /// state before a path is traced.
+    fn start_path(&mut self) {
+        self.dimension = 0;
+        seed_thread_rng(self.stream);
+    }
+
+    /// Accepts the proposed state with probability `accept`, otherwise returns to
+    /// the previous one. Returns whether it was accepted.
+    fn finish_iteration(&mut self, accept: f64) -> bool {
+        if self.rng.next_f64() < accept {
+            if self.large_step {
+                self.last_large_step = self.iteration;
+            }
+            return true;
+        }
+        for sample in &mut self.values {
+            if sample.last_modified == self.iteration {
+                sample.value = sample.backup;
+                sample.last_modified = sample.modify_backup;
+            }
+        }
+        if self.large_step {
+            self.stream = self.stream_backup;
+        }
+        self.iteration -= 1;
+        false
+    }
+
+    /// Value of the next dimension in the proposed state.
+    fn next(&mut self) -> f64 {
+        let i = self.dimension;
+        self.dimension += 1;
+        if i >= self.values.len() {
+            self.values.resize(i + 1, PrimarySample::default());
+        }
+        let sample = &mut self.values[i];
+        // Dimensions not requested since the last accepted large step still hold
+        // values from before it.
+        if sample.last_modified < self.last_large_step {
+            sample.value = self.rng.next_f64();
+            sample.last_modified = self.last_large_step;
+        }
+        sample.backup = sample.value;
+        sample.modify_backup = sample.last_modified;
+        if self.large_step {
+            sample.value = self.rng.next_f64();
+        } else {
+            // The skipped small steps add up to a single wider Gaussian step.
+            let steps = (self.iteration - sample.last_modified) as f64;
+            let (u1, u2) = (1.0 - self.rng.next_f64(), self.rng.next_f64());
+            let normal = (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+            sample.value += normal * self.mutation.sigma * steps.sqrt();
+            sample.value -= sample.value.floor();
+        }
+        sample.last_modified = self.iteration;
+        sample.value
+    }
+}
+
+/// Next dimension of the Metropolis chain traced on this thread, None outside of
+/// one.
+pub(crate) fn primary_sample() -> Option<f64> {
+    CHAIN.with_borrow_mut(|chain| chain.as_mut().map(PrimarySamples::next))
+}
+
+/// Runs `f` on the chain installed on this thread.
+fn with_chain<R>(f: impl FnOnce(&mut PrimarySamples) -> R) -> R {
+    CHAIN.with_borrow_mut(|chain| f(chain.as_mut().expect("no chain on this thread")))
+}
+
+/// Radiance of the path traced from the primary samples of the installed chain,
+/// and the pixel it lands in.
+#[derive(Debug, Clone, Copy)]
+struct Contribution {
+    x: usize,
+    y: usize,
+    color: Vector3<f64>,
+    luminance: f64,
+}
+
+/// Traces the path of the current state of the installed chain. The first two
+/// dimensions pick a position within `window`.
+fn trace(camera: &Camera, window: &Tile, world: &Hittable, lights: &Hittable) -> Contribution {
+    with_chain(PrimarySamples::start_path);
+    let u = get_2d();
+    let (fx, fy) = (u.x * window.width() as f64, u.y * window.height() as f64);
+    let x = (fx as usize).min(window.width() - 1);
+    let y = (fy as usize).min(window.height() - 1);
+    let (dx, dy) = (fx - x as f64 - 0.5, fy - y as f64 - 0.5);
+    let (x, y) = (window.x0 + x, window.y0 + y);
+    let color = match camera.get_ray(x as f64 + dx, y as f64 + dy) {
+        Some(ray) => Integrator::Path
+            .radiance(camera, &ray, world, lights, None, &mut vec![])
+            .total(),
+        None => Vector3::zeros(),
+    };
+    // Paths with invalid radiance are never accepted.
+    let luminance = luminance(&color);
+    let luminance = if luminance.is_finite() {
+        luminance.max(0.0)
+    } else {
+        0.0
+    };
+    Contribution {
+        x,
+        y,
+        color,
+        luminance,
+    }
+}
+
+/// Evaluates a chain's first state, with the chain installed on this thread.
+fn first_state(
+    samples: PrimarySamples,
+    camera: &Camera,
+    window: &Tile,
+    world: &Hittable,
+    lights: &Hittable,
+) -> (PrimarySamples, Contribution) {
+    CHAIN.set(Some(samples));
+    let contribution = trace(camera, window, world, lights);
+    (CHAIN.take().unwrap(), contribution)
+}
+
+struct Chain {
+    samples: PrimarySamples,
+    current: Contribution,
+}
+
+impl Chain {
+    /// Runs `mutations` steps of the chain, returning its splats scaled by the
+    /// normalization `b`. Both the proposed and the current state are splatted,
+    /// weighted by their acceptance, so rejected proposals still contribute.
+    fn run(
+        self,
+        mutations: usize,
+        b: f64,
+        camera: &Camera,
+        window: &Tile,
+        world: &Hittable,
+        lights: &Hittable,
+    ) -> (Self, Vec<LightSplat>) {
+        let mut current = self.current;
+        let mut splats = Vec::with_capacity(2 * mutations);
+        let splat = |c: &Contribution, weight: f64| LightSplat {
+            x: c.x,
+            y: c.y,
+            color: c.color * (weight * b / c.luminance),
+        };
+        CHAIN.set(Some(self.samples));
+        for _ in 0..mutations {
+            with_chain(PrimarySamples::start_iteration);
+            let proposed = trace(camera, window, world, lights);
+            let accept = (proposed.luminance / current.luminance).min(1.0);
+            if accept > 0.0 {
+                splats.push(splat(&proposed, accept));
+            }
+            if accept < 1.0 {
+                splats.push(splat(&current, 1.0 - accept));
+            }
+            if with_chain(|samples| samples.finish_iteration(accept)) {
+                current = proposed;
+            }
+        }
+        let samples = CHAIN.take().unwrap();
+        (Self { samples, current }, splats)
+    }
+}
+
+/// Renders `camera.samples_per_pixel` mutations per pixel of primary sample space
+/// Metropolis light transport into `film`.
+///
+/// The path tracer is run on `bootstrap_samples` independent states first, their
+/// mean luminance normalizes the image and `chains` starting states are picked from
+/// them in proportion to their luminance. Each chain then mutates the random numbers
+/// of its path as set by `mutation` and splats the result onto `film.light`, without
+/// weighting samples by the pixel filter.
+pub fn render(
+    camera: &Camera,
+    mut film: Film,
+    world: &Hittable,
+    lights: &Hittable,
+    bootstrap_samples: usize,
+    chains: usize,
+    mutation: Mutation,
+) -> Film {
+    let window = camera.crop_bounds(film.width, film.height);
+    let pixels = window.width() * window.height();
+
+    let bootstrap: Vec<f64> = (0..bootstrap_samples)
+        .into_par_iter()
+        .map(|i| {
+            let samples = PrimarySamples::new(hash(&[camera.seed, i as u64, 0]), mutation);
+            first_state(samples, camera, &window, world, lights)
+                .1
+                .luminance
+        })
+        .collect();
+    let total: f64 = bootstrap.iter().sum();
+    let mutations = camera.samples_per_pixel * pixels;
+    let chains = chains.clamp(1, mutations.max(1));
+
+    if total > 0.0 {
+        let b = total / bootstrap_samples as f64;
+        let cdf: Vec<f64> = bootstrap
+            .iter()
+            .scan(0.0, |sum, y| {
+                *sum += y;
+                Some(*sum / total)
+            })
+            .collect();
+        let mut states: Vec<Chain> = (0..chains)
+            .into_par_iter()
+            .map(|c| {
+                let u = Rng::new(hash(&[camera.seed, c as u64, 1]), 0).next_f64();
+                let i = cdf.partition_point(|&p| p <= u).min(bootstrap_samples - 1);
+                // Replaying the seed reproduces the bootstrap state, the chain then
+                // mutates it with a generator of its own.
+                let samples = PrimarySamples::new(hash(&[camera.seed, i as u64, 0]), mutation);
+                let (mut samples, current) = first_state(samples, camera, &window, world, lights);
+                samples.rng = Rng::new(hash(&[camera.seed, c as u64, 2]), 0);
+                Chain { samples, current }
+            })
+            .collect();
+
+        // Chains advance in rounds and their splats are added in chain order, so the
+        // result does not depend on thread scheduling.
+        let per_round = (MUTATIONS_PER_ROUND / chains).max(1);
+        let mut done = vec![0; chains];
+        let mut total_done = 0;
+        while total_done < mutations && !camera.cancel.is_cancelled() {
+            let steps: Vec<usize> = (0..chains)
+                .map(|c| {
+                    // The first chains take one more mutation when they do not divide
+                    // evenly.
+                    let share = mutations / chains + usize::from(c < mutations % chains);
+                    (share - done[c]).min(per_round)
+                })
+                .collect();
+            let (next, splats): (Vec<_>, Vec<_>) = states
+                .into_par_iter()
+                .zip(steps.clone())
+                .map(|(chain, n)| chain.run(n, b, camera, &window, world, lights))
+                .unzip();
+            states = next;
+            for splat in splats.iter().flatten() {
+                film.add_light_splat(splat);
+            }
+            for (c, n) in steps.iter().enumerate() {
+                done[c] += n;
+                total_done += n;
+            }
+            eprint!("\rMutations: {}/{}", total_done, mutations);
+        }
+        film.light_paths = total_done as f64 / pixels as f64;
+    }
+    eprintln!("\nDone.");
+
+    let samples = film.light_paths.round() as usize;
+    for idx in window.indices(film.width) {
+        film.samples[idx] = samples;
+    }
+    if camera.crop_window.is_some() && !camera.crop_full_frame {
+        return film.crop(window.x0, window.y0, window.width(), window.height());
+    }
+    film
+}
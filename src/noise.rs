@@ -2,7 +2,10 @@ use std::mem::swap;
 
 use nalgebra::Vector3;
 
-use crate::util::{random_f64, random_unit_vector, random_int, random_vec_range};
+use crate::{
+    rng::Rng,
+    util::{random_f64, random_u64, random_unit_vector},
+};
 
 pub const POINT_COUNT: usize = 256;
 #[derive(Debug, Clone)]
@@ -14,30 +17,37 @@ pub struct Perlin {
 }
 
 impl Perlin {
-    fn perlin_generate_perm() -> [usize; POINT_COUNT] {
+    fn perlin_generate_perm(rng: &mut Rng) -> [usize; POINT_COUNT] {
         let mut randflots = [0; POINT_COUNT];
         randflots
             .iter_mut()
             .enumerate()
             .for_each(|(idx, x)| *x = idx);
-        Perlin::permute(&mut randflots);
+        Perlin::permute(&mut randflots, rng);
         randflots
     }
-    fn permute(p: &mut [usize; POINT_COUNT]) {
+    fn permute(p: &mut [usize; POINT_COUNT], rng: &mut Rng) {
         for i in (1..POINT_COUNT).rev() {
-            let target = random_int(0, i as i32) as usize;
+            let target = ((rng.next_f64() * i as f64) as usize).min(i - 1);
             p.swap(target, i);
         }
     }
 
     pub fn new() -> Self {
+        Self::new_with_seed(random_u64())
+    }
+
+    /// Builds the noise tables from `seed`, equal seeds give identical noise.
+    pub fn new_with_seed(seed: u64) -> Self {
+        let mut rng = Rng::new(seed, 0);
         let mut randflots = [Vector3::zeros(); POINT_COUNT];
-        randflots
-            .iter_mut()
-            .for_each(|x| *x = random_vec_range(-1.0, 1.0).normalize());
-        let perm_x = Perlin::perlin_generate_perm();
-        let perm_y = Perlin::perlin_generate_perm();
-        let perm_z = Perlin::perlin_generate_perm();
+        randflots.iter_mut().for_each(|x| {
+            let v = Vector3::new(rng.next_f64(), rng.next_f64(), rng.next_f64());
+            *x = (2.0 * v - Vector3::repeat(1.0)).normalize()
+        });
+        let perm_x = Perlin::perlin_generate_perm(&mut rng);
+        let perm_y = Perlin::perlin_generate_perm(&mut rng);
+        let perm_z = Perlin::perlin_generate_perm(&mut rng);
         Self {
             randvec: randflots,
             perm_x,
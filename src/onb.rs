@@ -26,7 +26,7 @@ impl Onb {
         };
         let v = unit_w.cross(&a).normalize();
         let u = unit_w.cross(&v);
-        Self { u, v, w }
+        Self { u, v, w: unit_w }
     }
 }
 
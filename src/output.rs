@@ -0,0 +1,103 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use exr::prelude::f16;
+use image::{codecs::hdr::HdrEncoder, ImageError, ImageFormat, ImageResult, Rgb};
+
+use crate::film::Film;
+
+/// File format written by `Film::save_with_format`.
+///
+/// The HDR formats store the unclamped linear radiance, the LDR formats go through
+/// the film's tone mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Ldr(ImageFormat), // Any 8-bit format supported by the image crate
+    ExrHalf,          // OpenEXR, 16-bit half float channels
+    ExrFloat,         // OpenEXR, 32-bit float channels
+    Hdr,              // Radiance RGBE
+    Pfm,              // Portable float map
+}
+
+impl OutputFormat {
+    /// Picks the format from the file extension of `path`.
+    /// `.exr` files are written with half float channels.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> ImageResult<Self> {
+        let ext = path
+            .as_ref()
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+
+        match ext.as_deref() {
+            Some("exr") => Ok(OutputFormat::ExrHalf),
+            Some("hdr") => Ok(OutputFormat::Hdr),
+            Some("pfm") => Ok(OutputFormat::Pfm),
+            _ => ImageFormat::from_path(path).map(OutputFormat::Ldr),
+        }
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        !matches!(self, OutputFormat::Ldr(_))
+    }
+}
+
+fn exr_error(err: exr::error::Error) -> ImageError {
+    ImageError::IoError(io::Error::other(err))
+}
+
+pub fn write_exr_half<P: AsRef<Path>>(film: &Film, path: P) -> ImageResult<()> {
+    exr::prelude::write_rgb_file(path, film.width, film.height, |x, y| {
+        let color = film.pixel(x, y);
+        (
+            f16::from_f64(color.x),
+            f16::from_f64(color.y),
+            f16::from_f64(color.z),
+        )
+    })
+    .map_err(exr_error)
+}
+
+pub fn write_exr_float<P: AsRef<Path>>(film: &Film, path: P) -> ImageResult<()> {
+    exr::prelude::write_rgb_file(path, film.width, film.height, |x, y| {
+        let color = film.pixel(x, y);
+        (color.x as f32, color.y as f32, color.z as f32)
+    })
+    .map_err(exr_error)
+}
+
+pub fn write_hdr<P: AsRef<Path>>(film: &Film, path: P) -> ImageResult<()> {
+    let mut pixels = Vec::with_capacity(film.width * film.height);
+    for y in 0..film.height {
+        for x in 0..film.width {
+            let color = film.pixel(x, y);
+            // RGBE cannot store negative values.
+            pixels.push(Rgb([
+                color.x.max(0.0) as f32,
+                color.y.max(0.0) as f32,
+                color.z.max(0.0) as f32,
+            ]));
+        }
+    }
+    let writer = BufWriter::new(File::create(path)?);
+    HdrEncoder::new(writer).encode(&pixels, film.width, film.height)
+}
+
+pub fn write_pfm<P: AsRef<Path>>(film: &Film, path: P) -> ImageResult<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    // A negative scale marks little endian data, scanlines are stored bottom to top.
+    write!(writer, "PF\n{} {}\n-1.0\n", film.width, film.height)?;
+    for y in (0..film.height).rev() {
+        for x in 0..film.width {
+            let color = film.pixel(x, y);
+            for c in [color.x, color.y, color.z] {
+                writer.write_all(&(c as f32).to_le_bytes())?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
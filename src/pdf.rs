@@ -5,7 +5,8 @@ use nalgebra::Vector3;
 use crate::{
     hit::Hittable,
     onb::Onb,
-    util::{random_cosine_direction, random_f64, random_range_f64, random_unit_vector},
+    sampler::{get_1d, get_2d},
+    util::{cosine_direction, random_range_f64, random_unit_vector},
 };
 #[derive(Debug)]
 pub enum PDF<'a> {
@@ -81,7 +82,7 @@ impl CosinePdf {
     }
 
     pub fn generate(&self) -> Vector3<f64> {
-        self.uvw.local_v(random_cosine_direction())
+        self.uvw.local_v(cosine_direction(&get_2d()))
     }
 }
 
@@ -127,7 +128,7 @@ impl MixturePdf<'_> {
     }
 
     pub fn generate(&self) -> Vector3<f64> {
-        if random_f64() < 0.5 {
+        if get_1d() < 0.5 {
             self.p[0].generate()
         } else {
             self.p[1].generate()
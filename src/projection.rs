@@ -0,0 +1,189 @@
+use std::{f64::consts::PI, path::Path};
+
+use image::ImageResult;
+use nalgebra::Vector3;
+
+use crate::film::Film;
+
+/// Mapping from image positions to camera rays.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Projection {
+    /// Pinhole or thin lens camera with a vertical field of view of `vfov`.
+    #[default]
+    Perspective,
+    /// Parallel rays along the view direction, `height` is the vertical extent of
+    /// the view in world units.
+    Orthographic { height: f64 },
+    /// Full 360° by 180° latitude-longitude panorama around the camera, meant
+    /// for an aspect ratio of 2. The image center looks towards `lookat`.
+    Equirectangular,
+    /// Circular fisheye covering `fov` degrees (up to 360) across the image circle,
+    /// which is inscribed into the image. Pixels outside of it stay black.
+    Fisheye { mapping: FisheyeMapping, fov: f64 },
+    /// All six faces of a cube around the camera, arranged by `layout`. The image
+    /// height follows from the layout and `image_width`, `aspect_ratio` is ignored.
+    Cubemap { layout: CubemapLayout },
+}
+
+/// Relation between the angle off the view axis and the distance from the center
+/// of a fisheye image.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FisheyeMapping {
+    /// Distance proportional to the angle, r = f·θ.
+    #[default]
+    Equidistant,
+    /// Equal area, r = 2f·sin(θ/2).
+    Equisolid,
+}
+
+/// Arrangement of the six cube faces in the rendered image.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CubemapLayout {
+    /// Horizontal cross of 4x3 faces, the front face in the middle of the center row
+    /// with left, right and back to its sides and up and down above and below it.
+    #[default]
+    Cross,
+    /// Row of the faces +X, -X, +Y, -Y, +Z, -Z, to be split into six images with
+    /// `save_cubemap_faces`.
+    Faces,
+}
+
+/// Cube face with the camera space axes it looks along, to its right and up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CubeFace {
+    pub name: &'static str,
+    pub forward: Vector3<f64>,
+    pub right: Vector3<f64>,
+    pub up: Vector3<f64>,
+}
+
+/// The faces +X, -X, +Y, -Y, +Z, -Z in camera space, +Z being the view direction.
+pub const CUBE_FACES: [CubeFace; 6] = [
+    cube_face("px", [1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+    cube_face("nx", [-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+    cube_face("py", [0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+    cube_face("ny", [0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+    cube_face("pz", [0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    cube_face("nz", [0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+];
+
+const fn cube_face(
+    name: &'static str,
+    forward: [f64; 3],
+    right: [f64; 3],
+    up: [f64; 3],
+) -> CubeFace {
+    CubeFace {
+        name,
+        forward: Vector3::new(forward[0], forward[1], forward[2]),
+        right: Vector3::new(right[0], right[1], right[2]),
+        up: Vector3::new(up[0], up[1], up[2]),
+    }
+}
+
+/// Camera space direction (x right, y up, z forward) of the equirectangular image
+/// position s,t in [0,1]², the longitude running along s and the latitude along t.
+pub fn equirectangular_direction(s: f64, t: f64) -> Vector3<f64> {
+    let phi = (s - 0.5) * 2.0 * PI;
+    let theta = (0.5 - t) * PI;
+    Vector3::new(
+        theta.cos() * phi.sin(),
+        theta.sin(),
+        theta.cos() * phi.cos(),
+    )
+}
+
+/// Camera space direction of the fisheye image position x,y, given relative to the
+/// image circle with its center at 0,0, x pointing right and y up. `None` outside
+/// of the image circle.
+pub fn fisheye_direction(
+    mapping: FisheyeMapping,
+    fov: f64,
+    x: f64,
+    y: f64,
+) -> Option<Vector3<f64>> {
+    let r = (x * x + y * y).sqrt();
+    if r > 1.0 {
+        return None;
+    }
+    let half_fov = (fov.min(360.0) / 2.0).to_radians();
+    let theta = match mapping {
+        FisheyeMapping::Equidistant => r * half_fov,
+        FisheyeMapping::Equisolid => 2.0 * (r * (half_fov / 2.0).sin()).asin(),
+    };
+    let phi = y.atan2(x);
+    Some(Vector3::new(
+        theta.sin() * phi.cos(),
+        theta.sin() * phi.sin(),
+        theta.cos(),
+    ))
+}
+
+impl CubemapLayout {
+    /// Number of faces along the width and height of the image.
+    pub fn grid(&self) -> (usize, usize) {
+        match self {
+            CubemapLayout::Cross => (4, 3),
+            CubemapLayout::Faces => (6, 1),
+        }
+    }
+
+    /// Index into `CUBE_FACES` of the face at column `col` and row `row` of the grid.
+    pub fn face_at(&self, col: usize, row: usize) -> Option<usize> {
+        match self {
+            CubemapLayout::Cross => match (col, row) {
+                (1, 0) => Some(2),
+                (0, 1) => Some(1),
+                (1, 1) => Some(4),
+                (2, 1) => Some(0),
+                (3, 1) => Some(5),
+                (1, 2) => Some(3),
+                _ => None,
+            },
+            CubemapLayout::Faces => (row == 0 && col < 6).then_some(col),
+        }
+    }
+}
+
+/// Camera space direction of the cubemap image position s,t in [0,1]², `None` for
+/// the unused cells of the cross layout.
+pub fn cubemap_direction(layout: CubemapLayout, s: f64, t: f64) -> Option<Vector3<f64>> {
+    let (cols, rows) = layout.grid();
+    let (fs, ft) = (s * cols as f64, t * rows as f64);
+    let (col, row) = (fs.floor().max(0.0) as usize, ft.floor().max(0.0) as usize);
+    let face = CUBE_FACES[layout.face_at(col, row)?];
+    let a = 2.0 * (fs - col as f64) - 1.0;
+    let b = 1.0 - 2.0 * (ft - row as f64);
+    Some(face.forward + a * face.right + b * face.up)
+}
+
+/// Splits a cubemap rendered with `layout` into six films, ordered as `CUBE_FACES`.
+pub fn cubemap_faces(film: &Film, layout: CubemapLayout) -> Vec<Film> {
+    let (cols, rows) = layout.grid();
+    let size = film.width / cols;
+    (0..CUBE_FACES.len())
+        .map(|face| {
+            let (col, row) = (0..rows)
+                .flat_map(|row| (0..cols).map(move |col| (col, row)))
+                .find(|&(col, row)| layout.face_at(col, row) == Some(face))
+                .unwrap();
+            film.crop(col * size, row * size, size, size)
+        })
+        .collect()
+}
+
+/// Writes the six faces of a cubemap next to each other, e.g. `probe.px.exr` up to
+/// `probe.nz.exr` for `probe.exr`.
+pub fn save_cubemap_faces<P: AsRef<Path>>(
+    film: &Film,
+    layout: CubemapLayout,
+    path: P,
+) -> ImageResult<()> {
+    let path = path.as_ref();
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path.extension().unwrap_or_default().to_string_lossy();
+    for (face, face_film) in CUBE_FACES.iter().zip(cubemap_faces(film, layout)) {
+        face_film.save(path.with_file_name(format!("{}.{}.{}", stem, face.name, ext)))?;
+    }
+    Ok(())
+}
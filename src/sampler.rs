@@ -0,0 +1,358 @@
+use std::{cell::Cell, sync::OnceLock};
+
+use nalgebra::Vector2;
+
+use crate::{
+    mlt::primary_sample,
+    rng::{hash, Rng},
+    util::{random_f64, seed_thread_rng},
+};
+
+/// Generator of the sample dimensions consumed by each camera sample.
+///
+/// The first 2D dimension positions the sample inside its pixel, the following
+/// ones drive time, lens and the light/BSDF choices of every bounce in the order
+/// they are requested. Randomness not routed through the sampler (fuzz, Fresnel
+/// choices, media) comes from the independent per-sample stream. While a
+/// Metropolis chain is traced every dimension comes from its primary samples
+/// instead.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Sampler {
+    /// Uniform random values in every dimension.
+    Independent,
+    /// Jittered strata, each dimension's strata shuffled independently.
+    #[default]
+    Stratified,
+    /// Owen-scrambled Halton sequence, dimensions past the prime table fall back
+    /// to independent values.
+    Halton,
+    /// Owen-scrambled Sobol, padded from shuffled (0,2)-sequences so it works for
+    /// any number of dimensions. Best with a power of two samples per pixel.
+    Sobol,
+    /// Sobol sequence shared by all pixels, rotated per pixel by a blue noise
+    /// mask, so the remaining error is distributed as blue noise.
+    BlueNoise,
+}
+
+/// State of the camera sample currently traced on this thread.
+#[derive(Debug, Clone, Copy)]
+struct SampleState {
+    sampler: Sampler,
+    pixel: (u64, u64),
+    index: u64,
+    samples_per_pixel: u64,
+    seed: u64,
+    dimension: u64,
+}
+
+thread_local! {
+    static STATE: Cell<Option<SampleState>> = const { Cell::new(None) };
+}
+
+/// Starts sample `index` of the pixel at x,y, the following `get_1d` and `get_2d`
+/// calls on this thread return its dimensions. Also restarts the independent
+/// random stream, so the sample is reproducible regardless of scheduling.
+pub fn start_pixel_sample(
+    sampler: Sampler,
+    x: usize,
+    y: usize,
+    index: usize,
+    samples_per_pixel: usize,
+    seed: u64,
+) {
+    seed_thread_rng(hash(&[seed, x as u64, y as u64, index as u64]));
+    STATE.with(|state| {
+        state.set(Some(SampleState {
+            sampler,
+            pixel: (x as u64, y as u64),
+            index: index as u64,
+            samples_per_pixel: samples_per_pixel.max(1) as u64,
+            seed,
+            dimension: 0,
+        }))
+    });
+}
+
+/// Next 1D dimension of the current sample, uniform random outside of a sample.
+pub fn get_1d() -> f64 {
+    if let Some(value) = primary_sample() {
+        return value;
+    }
+    STATE.with(|state| match state.get() {
+        Some(mut s) => {
+            let value = s.sampler.sample_1d(&s);
+            s.dimension += 1;
+            state.set(Some(s));
+            value
+        }
+        None => random_f64(),
+    })
+}
+
+/// Next 2D dimension of the current sample, uniform random outside of a sample.
+pub fn get_2d() -> Vector2<f64> {
+    if let Some(x) = primary_sample() {
+        return Vector2::new(x, primary_sample().unwrap());
+    }
+    STATE.with(|state| match state.get() {
+        Some(mut s) => {
+            let value = s.sampler.sample_2d(&s);
+            s.dimension += 2;
+            state.set(Some(s));
+            value
+        }
+        None => Vector2::new(random_f64(), random_f64()),
+    })
+}
+
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+const PRIMES: [u64; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+impl Sampler {
+    fn sample_1d(&self, s: &SampleState) -> f64 {
+        let h = hash(&[s.pixel.0, s.pixel.1, s.dimension, s.seed]);
+        match self {
+            Sampler::Independent => random_f64(),
+            Sampler::Stratified => {
+                let stratum =
+                    permutation_element(s.index % s.samples_per_pixel, s.samples_per_pixel, h);
+                (stratum as f64 + random_f64()) / s.samples_per_pixel as f64
+            }
+            Sampler::Halton => halton(s.dimension, s.index, h),
+            Sampler::Sobol => {
+                let index = permutation_element(s.index, s.samples_per_pixel, h);
+                sobol(0, index, h >> 32)
+            }
+            Sampler::BlueNoise => {
+                // Same shuffle in every pixel, only the rotation differs between pixels.
+                let h = hash(&[s.dimension, s.seed]);
+                let index = permutation_element(s.index, s.samples_per_pixel, h);
+                (sobol(0, index, h >> 32) + blue_noise(s.pixel, h)).fract()
+            }
+        }
+    }
+
+    fn sample_2d(&self, s: &SampleState) -> Vector2<f64> {
+        let h = hash(&[s.pixel.0, s.pixel.1, s.dimension, s.seed]);
+        match self {
+            Sampler::Independent => Vector2::new(random_f64(), random_f64()),
+            Sampler::Stratified => {
+                // Wraps around the largest square grid fitting into the sample count.
+                let n = (s.samples_per_pixel as f64).sqrt() as u64;
+                let stratum = permutation_element(s.index % (n * n), n * n, h);
+                Vector2::new(
+                    ((stratum % n) as f64 + random_f64()) / n as f64,
+                    ((stratum / n) as f64 + random_f64()) / n as f64,
+                )
+            }
+            Sampler::Halton => Vector2::new(
+                halton(s.dimension, s.index, h),
+                halton(s.dimension + 1, s.index, mix_hash(h)),
+            ),
+            Sampler::Sobol => {
+                let index = permutation_element(s.index, s.samples_per_pixel, h);
+                Vector2::new(sobol(0, index, h & 0xffff_ffff), sobol(1, index, h >> 32))
+            }
+            Sampler::BlueNoise => {
+                let h = hash(&[s.dimension, s.seed]);
+                let index = permutation_element(s.index, s.samples_per_pixel, h);
+                let shift = Vector2::new(blue_noise(s.pixel, h), blue_noise(s.pixel, mix_hash(h)));
+                Vector2::new(
+                    (sobol(0, index, h & 0xffff_ffff) + shift.x).fract(),
+                    (sobol(1, index, h >> 32) + shift.y).fract(),
+                )
+            }
+        }
+    }
+}
+
+fn mix_hash(h: u64) -> u64 {
+    hash(&[h])
+}
+
+/// Element `i` of a pseudo-random permutation of [0,n) selected by `seed`
+/// (Kensler, "Correlated Multi-Jittered Sampling").
+fn permutation_element(i: u64, n: u64, seed: u64) -> u64 {
+    let (l, p) = (n as u32, seed as u32);
+    let mut i = i as u32;
+    let mut w = l.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p) % l) as u64
+}
+
+/// Radical inverse of `a` in the prime base of `dimension`, with the digits
+/// Owen-scrambled by `seed`.
+fn halton(dimension: u64, mut a: u64, seed: u64) -> f64 {
+    let Some(&base) = PRIMES.get(dimension as usize) else {
+        return random_f64();
+    };
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0;
+    let mut reversed = 0u64;
+    // Scramble enough digits to cover the full f64 precision, or as many as the
+    // reversed digits can hold in large bases.
+    while 1.0 - (base - 1) as f64 * inv_base_m < 1.0 && reversed < u64::MAX / base {
+        let next = a / base;
+        let digit = a - next * base;
+        let digit = permutation_element(digit, base, hash(&[seed, reversed]));
+        reversed = reversed * base + digit;
+        inv_base_m *= inv_base;
+        a = next;
+    }
+    (reversed as f64 * inv_base_m).min(ONE_MINUS_EPSILON)
+}
+
+/// Dimension 0 or 1 of the Sobol sequence at `index`, Owen-scrambled by `seed`.
+fn sobol(dimension: u32, index: u64, seed: u64) -> f64 {
+    let index = index as u32;
+    let value = if dimension == 0 {
+        index.reverse_bits()
+    } else {
+        // Kollig and Keller, "Efficient Multidimensional Sampling".
+        let (mut i, mut v, mut r) = (index, 1u32 << 31, 0u32);
+        while i != 0 {
+            if i & 1 != 0 {
+                r ^= v;
+            }
+            i >>= 1;
+            v ^= v >> 1;
+        }
+        r
+    };
+    (owen_scramble(value, seed as u32) as f64 / 4294967296.0).min(ONE_MINUS_EPSILON)
+}
+
+/// Hash based nested uniform scrambling of a 32-bit fixed point value
+/// (Laine and Karras, "Stratified Sampling for Stochastic Transparency").
+fn owen_scramble(v: u32, seed: u32) -> u32 {
+    let mut v = v.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x05526c56);
+    v ^= v.wrapping_mul(0x53a22864);
+    v.reverse_bits()
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// Value of the tiled blue noise mask at `pixel`, toroidally shifted by `seed`
+/// so every dimension sees a different mask.
+fn blue_noise(pixel: (u64, u64), seed: u64) -> f64 {
+    static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+    let mask = MASK.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE, 1.5, 0));
+    let size = BLUE_NOISE_SIZE as u64;
+    let x = (pixel.0 + seed % size) % size;
+    let y = (pixel.1 + (seed >> 32) % size) % size;
+    mask[(y * size + x) as usize]
+}
+
+/// Generates a tileable `size`x`size` blue noise mask with the void-and-cluster
+/// method (Ulichney 1993), returning the normalized rank of every pixel.
+fn void_and_cluster(size: usize, sigma: f64, seed: u64) -> Vec<f64> {
+    let n = size * size;
+    let kernel: Vec<f64> = (0..n)
+        .map(|k| {
+            let (dx, dy) = (k % size, k / size);
+            let (dx, dy) = (dx.min(size - dx) as f64, dy.min(size - dy) as f64);
+            (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+    let splat = |energy: &mut [f64], idx: usize, sign: f64| {
+        let (x, y) = (idx % size, idx / size);
+        for (k, e) in energy.iter_mut().enumerate() {
+            let dx = (k % size + size - x) % size;
+            let dy = (k / size + size - y) % size;
+            *e += sign * kernel[dy * size + dx];
+        }
+    };
+    // Tightest cluster among the set pixels, largest void among the unset ones.
+    let extreme = |pattern: &[bool], energy: &[f64], set: bool| {
+        (0..n)
+            .filter(|&k| pattern[k] == set)
+            .max_by(|&a, &b| {
+                let (ea, eb) = if set {
+                    (energy[a], energy[b])
+                } else {
+                    (energy[b], energy[a])
+                };
+                ea.total_cmp(&eb)
+            })
+            .unwrap()
+    };
+
+    let mut rng = Rng::new(seed, 0);
+    let mut pattern = vec![false; n];
+    let mut energy = vec![0.0; n];
+    let initial = n / 10;
+    let mut count = 0;
+    while count < initial {
+        let idx = rng.next_u32() as usize % n;
+        if !pattern[idx] {
+            pattern[idx] = true;
+            splat(&mut energy, idx, 1.0);
+            count += 1;
+        }
+    }
+    // Move points from clusters into voids until the pattern is evenly spread.
+    loop {
+        let cluster = extreme(&pattern, &energy, true);
+        pattern[cluster] = false;
+        splat(&mut energy, cluster, -1.0);
+        let void = extreme(&pattern, &energy, false);
+        pattern[void] = true;
+        splat(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0; n];
+    let (mut ones, mut ones_energy) = (pattern.clone(), energy.clone());
+    for r in (0..initial).rev() {
+        let cluster = extreme(&ones, &ones_energy, true);
+        ones[cluster] = false;
+        splat(&mut ones_energy, cluster, -1.0);
+        rank[cluster] = r;
+    }
+    for r in initial..n {
+        let void = extreme(&pattern, &energy, false);
+        pattern[void] = true;
+        splat(&mut energy, void, 1.0);
+        rank[void] = r;
+    }
+    rank.iter().map(|&r| (r as f64 + 0.5) / n as f64).collect()
+}
@@ -3,19 +3,21 @@ use nalgebra::Vector3;
 use crate::{
     aabb::{AABB, AABB_EMPTY},
     hit::{HitRecord, Hittable},
-    util::{random_int, Interval},
+    sampler::get_1d,
+    util::Interval,
 };
 #[derive(Debug, Default, Clone)]
 pub struct Scene {
     pub objects: Vec<Hittable>,
     pub bbox: AABB,
+    pub object_ids: usize, // Object IDs handed out to the primitives added so far
 }
 
 impl Scene {
     pub fn hit(&self, ray: &crate::ray::Ray, interval: &Interval) -> Option<HitRecord> {
         let mut hit_record = None;
         let mut closest_so_far = interval.max;
-        for obj in &self.objects {
+        for obj in self.objects.iter() {
             if let Some(record) = obj.hit(ray, &Interval::new(interval.min, closest_so_far)) {
                 closest_so_far = record.t;
                 hit_record.replace(record);
@@ -24,7 +26,10 @@ impl Scene {
         hit_record
     }
 
-    pub fn add(&mut self, obj: Hittable) {
+    /// Adds `obj`, its primitives get object IDs following those already in the scene.
+    pub fn add(&mut self, mut obj: Hittable) {
+        obj.offset_object_ids(self.object_ids);
+        self.object_ids += obj.object_ids();
         self.bbox = AABB::merge(&self.bbox, obj.bbox());
         self.objects.push(obj);
     }
@@ -32,6 +37,7 @@ impl Scene {
         let mut x = Self {
             objects: vec![],
             bbox: AABB_EMPTY,
+            object_ids: 0,
         };
         for obj in objects {
             x.add(obj);
@@ -61,6 +67,15 @@ impl Scene {
     pub fn random(&self, origin: &Vector3<f64>) -> Vector3<f64> {
         let int_size = self.objects.len() as i32;
         // println!("int_size:{}", int_size);
-        self.objects[random_int(0, int_size ) as usize].random(origin)
+        let pick = ((get_1d() * int_size as f64) as usize).min(self.objects.len() - 1);
+        self.objects[pick].random(origin)
+    }
+    pub fn sample_point(&self, time: f64) -> Option<(HitRecord<'_>, f64)> {
+        if self.objects.is_empty() {
+            return None;
+        }
+        let pick = ((get_1d() * self.objects.len() as f64) as usize).min(self.objects.len() - 1);
+        let (rec, pdf) = self.objects[pick].sample_point(time)?;
+        Some((rec, pdf / self.objects.len() as f64))
     }
 }
@@ -0,0 +1,64 @@
+/// Efficiency of the shutter over the time it is open, which weights how much
+/// each instant contributes to the motion blur.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum ShutterCurve {
+    /// Fully open for the whole interval.
+    #[default]
+    Box,
+    /// Opens linearly over the first `ramp` fraction of the interval and closes
+    /// over the last one.
+    Trapezoid { ramp: f64 },
+    /// Piecewise linear efficiency through (position, efficiency) points, positions
+    /// ascending in [0,1] across the open interval.
+    Custom(Vec<(f64, f64)>),
+}
+
+/// Offset in [0,1] into the linear density through a at 0 and b at 1 for the
+/// uniform sample `u`.
+fn sample_linear(u: f64, a: f64, b: f64) -> f64 {
+    if (b - a).abs() <= 1e-9 * (a + b) {
+        return u;
+    }
+    let x = (-a + (a * a + (b - a) * u * (a + b)).max(0.0).sqrt()) / (b - a);
+    x.clamp(0.0, 1.0)
+}
+
+/// Position in [0,1] distributed by the piecewise linear curve through `points`.
+fn sample_curve(points: &[(f64, f64)], u: f64) -> f64 {
+    let area = |(x0, e0): (f64, f64), (x1, e1): (f64, f64)| 0.5 * (e0 + e1) * (x1 - x0);
+    let total: f64 = points.windows(2).map(|w| area(w[0], w[1])).sum();
+    if total <= 0.0 {
+        return u;
+    }
+
+    let mut target = u * total;
+    let segments = points.len() - 1;
+    for (i, w) in points.windows(2).enumerate() {
+        let segment = area(w[0], w[1]);
+        if target < segment || i + 1 == segments {
+            let t = if segment > 0.0 {
+                sample_linear((target / segment).min(1.0), w[0].1, w[1].1)
+            } else {
+                1.0
+            };
+            return w[0].0 + t * (w[1].0 - w[0].0);
+        }
+        target -= segment;
+    }
+    u
+}
+
+impl ShutterCurve {
+    /// Maps the uniform sample `u` to a position in [0,1] across the open interval,
+    /// distributed proportionally to the efficiency.
+    pub fn sample(&self, u: f64) -> f64 {
+        match self {
+            ShutterCurve::Box => u,
+            ShutterCurve::Trapezoid { ramp } => {
+                let ramp = ramp.clamp(0.0, 0.5);
+                sample_curve(&[(0.0, 0.0), (ramp, 1.0), (1.0 - ramp, 1.0), (1.0, 0.0)], u)
+            }
+            ShutterCurve::Custom(points) => sample_curve(points, u),
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use std::{collections::HashMap, f64::consts::PI};
+
+use nalgebra::Vector3;
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    camera::Camera,
+    film::Film,
+    hit::{HitRecord, Hittable},
+    integrator::emitted_along,
+    onb::Onb,
+    ray::Ray,
+    sampler::{get_2d, start_pixel_sample, Sampler},
+    util::{cosine_direction, random_f64, Interval},
+};
+
+/// Fraction of the photons of an iteration kept when the gather radius shrinks.
+const ALPHA: f64 = 2.0 / 3.0;
+
+/// Photons traced in a row by one worker.
+const PHOTON_CHUNK: usize = 4096;
+
+/// First non-specular hit of a camera path, where photons are gathered.
+struct VisiblePoint<'a> {
+    rec: HitRecord<'a>,
+    ray: Ray,           // Camera path ray that reached the hit
+    beta: Vector3<f64>, // Throughput of the camera path up to the hit
+}
+
+/// Photon mapping state of one pixel, carried across iterations.
+#[derive(Debug, Clone, Copy)]
+struct SppmPixel {
+    direct: Vector3<f64>, // Sum of the light found by the camera paths themselves
+    radius: f64,          // Gather radius
+    photons: f64,         // Photon count the radius was shrunk for
+    flux: Vector3<f64>,   // Flux gathered within the current radius
+}
+
+/// Uniform grid over the visible points, each cell lists the points whose gather
+/// sphere overlaps it.
+struct Grid {
+    cell_size: f64,
+    cells: HashMap<[i64; 3], Vec<usize>>,
+}
+
+impl Grid {
+    /// Builds the grid from the position and gather radius of every point.
+    fn new(points: &[Option<(Vector3<f64>, f64)>]) -> Self {
+        let max_radius = points.iter().flatten().map(|(_, r)| *r).fold(0.0, f64::max);
+        let mut grid = Self {
+            cell_size: 2.0 * max_radius,
+            cells: HashMap::new(),
+        };
+        if max_radius <= 0.0 {
+            return grid;
+        }
+        for (k, point) in points.iter().enumerate() {
+            let Some((p, r)) = *point else {
+                continue;
+            };
+            let (lo, hi) = (
+                grid.cell(&(p - Vector3::repeat(r))),
+                grid.cell(&(p + Vector3::repeat(r))),
+            );
+            for x in lo[0]..=hi[0] {
+                for y in lo[1]..=hi[1] {
+                    for z in lo[2]..=hi[2] {
+                        grid.cells.entry([x, y, z]).or_default().push(k);
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    fn cell(&self, p: &Vector3<f64>) -> [i64; 3] {
+        [0, 1, 2].map(|i| (p[i] / self.cell_size).floor() as i64)
+    }
+
+    /// Points whose gather sphere may contain `p`.
+    fn candidates(&self, p: &Vector3<f64>) -> &[usize] {
+        if self.cells.is_empty() {
+            return &[];
+        }
+        self.cells
+            .get(&self.cell(p))
+            .map_or(&[], |cell| cell.as_slice())
+    }
+}
+
+/// Follows the camera ray `r` through specular bounces to its first non-specular hit.
+/// Returns the light found on the way, including direct light at the hit, and the
+/// hit itself.
+fn camera_path<'a>(
+    camera: &Camera,
+    r: Ray,
+    world: &'a Hittable,
+    lights: &Hittable,
+) -> (Vector3<f64>, Option<VisiblePoint<'a>>) {
+    let mut light = Vector3::zeros();
+    let mut beta = Vector3::repeat(1.0);
+    let mut ray = r;
+    for bounce in 0..camera.max_depth {
+        let Some(rec) = world.hit(&ray, &Interval::new(0.001, f64::INFINITY)) else {
+            light += beta.component_mul(&camera.background);
+            break;
+        };
+        // Only camera rays and specular bounces get here, emission is not found by
+        // any other strategy.
+        light += beta.component_mul(&rec.material.emitted(&rec.uv, &rec.p, &rec));
+        let Some(srec) = rec.material.scatter(&ray, &rec) else {
+            break;
+        };
+        if srec.skip_pdf {
+            beta = beta.component_mul(&srec.attenuation);
+            ray = srec.skip_pdf_ray;
+            continue;
+        }
+
+        // Direct light is sampled here, photons only bring light that has bounced.
+        if bounce + 1 < camera.max_depth {
+            let direction = lights.random(&rec.p);
+            let light_pdf = lights.pdf_value(&rec.p, &direction);
+            let shadow_ray = Ray::new_with_time(rec.p, direction, ray.time);
+            let scattering_pdf = rec.material.scattering_pdf(&ray, &shadow_ray, &rec);
+            if light_pdf > 0.0 && scattering_pdf > 0.0 {
+                let le = emitted_along(&shadow_ray, world, camera.background);
+                let weight = srec.attenuation * scattering_pdf / light_pdf;
+                light += beta.component_mul(&weight).component_mul(&le);
+            }
+        }
+        return (light, Some(VisiblePoint { rec, ray, beta }));
+    }
+    (light, None)
+}
+
+/// Traces one photon from a point picked by area on the lights and adds its flux at
+/// every visible point it lands on to `gathered`, counting the photon there too.
+fn trace_photon(
+    camera: &Camera,
+    world: &Hittable,
+    lights: &Hittable,
+    grid: &Grid,
+    visible: &[(Vector3<f64>, Option<VisiblePoint>)],
+    pixels: &[SppmPixel],
+    gathered: &mut [(Vector3<f64>, usize)],
+) {
+    let time = camera.sample_time();
+    let Some((rec, pdf_pos)) = lights.sample_point(time) else {
+        return;
+    };
+    let le = rec.material.emitted(&rec.uv, &rec.p, &rec);
+    if pdf_pos <= 0.0 || le == Vector3::zeros() {
+        return;
+    }
+    let direction = Onb::new_from_w(rec.normal).local_v(cosine_direction(&get_2d()));
+    // Cosine weighted emission cancels the cosine of the emitted radiance.
+    let mut beta = le * PI / pdf_pos;
+    let mut ray = Ray::new_with_time(rec.p, direction, time);
+
+    for bounce in 0..camera.max_depth {
+        let Some(rec) = world.hit(&ray, &Interval::new(0.001, f64::INFINITY)) else {
+            break;
+        };
+        let Some(srec) = rec.material.scatter(&ray, &rec) else {
+            break;
+        };
+        // The first hit is direct light, which the camera paths sample themselves.
+        if !srec.skip_pdf && bounce > 0 {
+            for &k in grid.candidates(&rec.p) {
+                let Some(vp) = &visible[k].1 else {
+                    continue;
+                };
+                let radius = pixels[k].radius;
+                if (vp.rec.p - rec.p).norm_squared() <= radius * radius {
+                    let f = vp.rec.material.bsdf(&vp.ray, &vp.rec, &-ray.direction);
+                    gathered[k].0 += beta.component_mul(&f);
+                    gathered[k].1 += 1;
+                }
+            }
+        }
+
+        let (scattered, weight) = if srec.skip_pdf {
+            (srec.skip_pdf_ray, srec.attenuation)
+        } else {
+            let direction = srec.pdf.generate();
+            let pdf = srec.pdf.value(&direction);
+            if pdf <= 0.0 {
+                break;
+            }
+            let scattered = Ray::new_with_time(rec.p, direction, ray.time);
+            let scattering_pdf = rec.material.scattering_pdf(&ray, &scattered, &rec);
+            (scattered, srec.attenuation * scattering_pdf / pdf)
+        };
+        let scattered_beta = beta.component_mul(&weight);
+
+        // Photons survive with the fraction of flux the bounce kept.
+        if camera.roulette_depth > 0 && bounce >= camera.roulette_depth {
+            let survival = (scattered_beta.max() / beta.max()).min(1.0);
+            if random_f64() >= survival {
+                break;
+            }
+            beta = scattered_beta / survival;
+        } else {
+            beta = scattered_beta;
+        }
+        ray = scattered;
+    }
+}
+
+/// Renders `camera.samples_per_pixel` iterations of stochastic progressive photon
+/// mapping into `film`.
+///
+/// Each iteration traces one camera path per pixel to its first non-specular hit,
+/// then `photons_per_pass` photons emitted from points picked by area on the lights,
+/// so every emitting surface takes part. Photons landing within a pixel's gather
+/// radius of its hit add their flux, and the radius, starting at `initial_radius`,
+/// shrinks with the photons gathered so the estimate converges. Light from the
+/// background is only seen directly or through specular bounces.
+pub fn render(
+    camera: &Camera,
+    mut film: Film,
+    world: &Hittable,
+    lights: &Hittable,
+    photons_per_pass: usize,
+    initial_radius: f64,
+) -> Film {
+    let window = camera.crop_bounds(film.width, film.height);
+    let indices: Vec<usize> = window.indices(film.width).collect();
+    let mut pixels = vec![
+        SppmPixel {
+            direct: Vector3::zeros(),
+            radius: initial_radius,
+            photons: 0.0,
+            flux: Vector3::zeros(),
+        };
+        indices.len()
+    ];
+    let iterations = camera.samples_per_pixel;
+    let mut iteration = 0;
+
+    while iteration < iterations && !camera.cancel.is_cancelled() {
+        let visible: Vec<_> = indices
+            .par_iter()
+            .map(|&idx| {
+                let (i, j) = (idx % film.width, idx / film.width);
+                start_pixel_sample(camera.sampler, i, j, iteration, iterations, camera.seed);
+                let u = get_2d();
+                match camera.get_ray(i as f64 + u.x - 0.5, j as f64 + u.y - 0.5) {
+                    Some(ray) => camera_path(camera, ray, world, lights),
+                    None => (Vector3::zeros(), None),
+                }
+            })
+            .collect();
+        let points: Vec<_> = visible
+            .iter()
+            .zip(&pixels)
+            .map(|((_, vp), pixel)| vp.as_ref().map(|vp| (vp.rec.p, pixel.radius)))
+            .collect();
+        let grid = Grid::new(&points);
+
+        // Photons are traced in chunks, one batch of chunks per thread at a time. Each
+        // chunk gathers into arrays of its own, added in chunk order so the result
+        // does not depend on thread scheduling.
+        let mut gathered = vec![(Vector3::zeros(), 0usize); pixels.len()];
+        let chunks = photons_per_pass.div_ceil(PHOTON_CHUNK);
+        let batch = rayon::current_num_threads().max(1);
+        for first in (0..chunks).step_by(batch) {
+            let chunk_gathered: Vec<_> = (first..(first + batch).min(chunks))
+                .into_par_iter()
+                .map(|chunk| {
+                    let mut gathered = vec![(Vector3::zeros(), 0usize); pixels.len()];
+                    let end = ((chunk + 1) * PHOTON_CHUNK).min(photons_per_pass);
+                    for photon in chunk * PHOTON_CHUNK..end {
+                        // Photons get random streams of their own, keyed below the last
+                        // image row.
+                        start_pixel_sample(
+                            Sampler::Independent,
+                            photon,
+                            film.height,
+                            iteration,
+                            1,
+                            camera.seed,
+                        );
+                        trace_photon(
+                            camera,
+                            world,
+                            lights,
+                            &grid,
+                            &visible,
+                            &pixels,
+                            &mut gathered,
+                        );
+                    }
+                    gathered
+                })
+                .collect();
+            for chunk in chunk_gathered {
+                for (sum, (phi, count)) in gathered.iter_mut().zip(chunk) {
+                    sum.0 += phi;
+                    sum.1 += count;
+                }
+            }
+        }
+        for (k, pixel) in pixels.iter_mut().enumerate() {
+            pixel.direct += visible[k].0;
+            let Some(vp) = &visible[k].1 else {
+                continue;
+            };
+            let (phi, count) = gathered[k];
+            if count == 0 {
+                continue;
+            }
+            // Keep only a fraction of the new photons and shrink the radius to match,
+            // the flux gathered so far is scaled down with the area.
+            let m = count as f64;
+            let photons = pixel.photons + ALPHA * m;
+            let radius = pixel.radius * (photons / (pixel.photons + m)).sqrt();
+            pixel.flux = (pixel.flux + vp.beta.component_mul(&phi))
+                * (radius * radius / (pixel.radius * pixel.radius));
+            pixel.photons = photons;
+            pixel.radius = radius;
+        }
+
+        iteration += 1;
+        eprint!("\rIterations: {}", iteration);
+    }
+    eprintln!("\nDone.");
+
+    if iteration > 0 {
+        let emitted = (iteration * photons_per_pass) as f64;
+        for (k, &idx) in indices.iter().enumerate() {
+            let pixel = &pixels[k];
+            let area = PI * pixel.radius * pixel.radius;
+            // Every iteration counts as one sample of weight one.
+            film.color[idx] = pixel.direct + pixel.flux * iteration as f64 / (emitted * area);
+            film.weight[idx] = iteration as f64;
+            film.samples[idx] = iteration;
+        }
+    }
+    if camera.crop_window.is_some() && !camera.crop_full_frame {
+        return film.crop(window.x0, window.y0, window.width(), window.height());
+    }
+    film
+}
@@ -0,0 +1,196 @@
+use std::path::Path;
+
+use image::ImageResult;
+use nalgebra::Vector2;
+
+use crate::{
+    camera::{Camera, CameraPose},
+    film::Film,
+    hit::Hittable,
+    keyframe::Keyframes,
+};
+
+/// How the optical axes of the two eyes are arranged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Convergence {
+    /// Parallel axes with the viewports shifted towards each other, the
+    /// distortion free setup.
+    #[default]
+    Parallel,
+    /// Both eyes rotated to look at the convergence point. Simpler, but introduces
+    /// vertical parallax towards the image corners.
+    ToedIn,
+}
+
+/// Arrangement of the two eye images in a single packed frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StereoLayout {
+    /// Left eye on the left half, right eye on the right half.
+    #[default]
+    SideBySide,
+    /// Left eye on the top half, right eye on the bottom half.
+    OverUnder,
+}
+
+/// One of the two eyes of a `StereoRig`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+/// Pair of eyes placed around the camera's pose, each rendered with the camera's
+/// own lens, sampler and film settings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StereoRig {
+    pub interocular: f64,      // Distance between the two eye centers
+    pub convergence_dist: f64, // Distance of the zero parallax plane from the camera
+    pub convergence: Convergence,
+}
+
+impl Default for StereoRig {
+    fn default() -> Self {
+        Self {
+            interocular: 0.065,
+            convergence_dist: 10.0,
+            convergence: Convergence::default(),
+        }
+    }
+}
+
+/// Rendered left and right eye images.
+#[derive(Debug, Clone)]
+pub struct StereoFilms {
+    pub left: Film,
+    pub right: Film,
+}
+
+impl StereoRig {
+    /// Pose of `eye` for the center camera `pose`.
+    pub fn eye_pose(&self, pose: &CameraPose, eye: Eye) -> CameraPose {
+        let forward = (pose.lookat - pose.lookfrom).normalize();
+        let right = forward.cross(&pose.vup).normalize();
+        let offset = self.eye_sign(eye) * 0.5 * self.interocular * right;
+        let lookat = match self.convergence {
+            Convergence::Parallel => pose.lookat + offset,
+            Convergence::ToedIn => pose.lookfrom + self.convergence_dist * forward,
+        };
+        CameraPose {
+            lookfrom: pose.lookfrom + offset,
+            lookat,
+            vup: pose.vup,
+        }
+    }
+
+    /// Lens shift of `eye` on a camera with the vertical field of view `vfov`, which
+    /// moves the convergence point back onto the image center.
+    pub fn eye_lens_shift(&self, vfov: f64, eye: Eye) -> Vector2<f64> {
+        match self.convergence {
+            Convergence::Parallel => {
+                let viewport_height = 2.0 * self.convergence_dist * (vfov.to_radians() / 2.0).tan();
+                Vector2::new(
+                    -self.eye_sign(eye) * 0.5 * self.interocular / viewport_height,
+                    0.0,
+                )
+            }
+            Convergence::ToedIn => Vector2::zeros(),
+        }
+    }
+
+    fn eye_sign(&self, eye: Eye) -> f64 {
+        match eye {
+            Eye::Left => -1.0,
+            Eye::Right => 1.0,
+        }
+    }
+
+    /// Renders one eye, `camera` is restored afterwards.
+    pub fn render_eye(
+        &self,
+        camera: &mut Camera,
+        eye: Eye,
+        world: Hittable,
+        lights: Hittable,
+    ) -> Film {
+        let (lookfrom, lookat, vup) = (camera.lookfrom, camera.lookat, camera.vup);
+        let (lens_shift, motion) = (camera.lens_shift, camera.motion.clone());
+
+        let pose = self.eye_pose(
+            &CameraPose {
+                lookfrom,
+                lookat,
+                vup,
+            },
+            eye,
+        );
+        (camera.lookfrom, camera.lookat, camera.vup) = (pose.lookfrom, pose.lookat, pose.vup);
+        camera.lens_shift = lens_shift + self.eye_lens_shift(camera.vfov, eye);
+        let mut eye_motion = Keyframes::new();
+        for (time, pose) in motion.keys() {
+            eye_motion.insert(*time, self.eye_pose(pose, eye));
+        }
+        camera.motion = eye_motion;
+
+        let film = camera.render(world, lights);
+
+        (camera.lookfrom, camera.lookat, camera.vup) = (lookfrom, lookat, vup);
+        (camera.lens_shift, camera.motion) = (lens_shift, motion);
+        film
+    }
+
+    /// Renders the left and then the right eye.
+    pub fn render(&self, camera: &mut Camera, world: Hittable, lights: Hittable) -> StereoFilms {
+        eprintln!("Left eye");
+        let left = self.render_eye(camera, Eye::Left, world.clone(), lights.clone());
+        eprintln!("Right eye");
+        let right = self.render_eye(camera, Eye::Right, world, lights);
+        StereoFilms { left, right }
+    }
+}
+
+impl StereoFilms {
+    /// Packs both eyes into one film at full resolution each.
+    pub fn pack(&self, layout: StereoLayout) -> Film {
+        let (width, height) = (self.left.width, self.left.height);
+        let (packed_width, packed_height, x1, y1) = match layout {
+            StereoLayout::SideBySide => (2 * width, height, width, 0),
+            StereoLayout::OverUnder => (width, 2 * height, 0, height),
+        };
+        let mut film = Film::new(packed_width, packed_height);
+        film.tone_mapping = self.left.tone_mapping;
+        if self.left.aovs.is_some() {
+            film.enable_aovs();
+        }
+        // Light splats of the packed film are normalized by a single light path count,
+        // so those of the right eye are rescaled to the count of the left one.
+        film.light_paths = if self.left.light_paths > 0.0 {
+            self.left.light_paths
+        } else {
+            self.right.light_paths
+        };
+        let mut right = self.right.clone();
+        if right.light_paths > 0.0 {
+            let scale = film.light_paths / right.light_paths;
+            right.light.iter_mut().for_each(|light| *light *= scale);
+        }
+        film.merge(&self.left, 0, 0);
+        film.merge(&right, x1 as isize, y1 as isize);
+        film
+    }
+
+    /// Writes `<stem>.left.<ext>` and `<stem>.right.<ext>` next to `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> ImageResult<()> {
+        let path = path.as_ref();
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let ext = path.extension().unwrap_or_default().to_string_lossy();
+        self.left
+            .save(path.with_file_name(format!("{}.left.{}", stem, ext)))?;
+        self.right
+            .save(path.with_file_name(format!("{}.right.{}", stem, ext)))
+    }
+
+    /// Writes both eyes packed into the single image `path`.
+    pub fn save_packed<P: AsRef<Path>>(&self, path: P, layout: StereoLayout) -> ImageResult<()> {
+        self.pack(layout).save(path)
+    }
+}
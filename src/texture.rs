@@ -115,6 +115,12 @@ impl NoiseTexture {
             scale,
         }
     }
+    pub fn new_with_seed(scale: f64, seed: u64) -> Self {
+        Self {
+            noise: Perlin::new_with_seed(seed),
+            scale,
+        }
+    }
     pub fn value(&self, _uv: &Vector2<f64>, p: &Vector3<f64>) -> Vector3<f64> {
         // let noise = self.noise.noise(&(p * self.scale)) * 0.5 + 0.5;
         let noise: f64 = 0.5 * (1.0 + (self.scale * p.z + 10.0 * self.noise.turb(p, 7)).sin());
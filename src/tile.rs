@@ -0,0 +1,180 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Rectangle of pixels rendered as one unit of work, `x1` and `y1` are exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Tile {
+    pub fn width(&self) -> usize {
+        self.x1 - self.x0
+    }
+    pub fn height(&self) -> usize {
+        self.y1 - self.y0
+    }
+    /// Film indices of the pixels in the tile, for a film `film_width` pixels wide.
+    pub fn indices(&self, film_width: usize) -> impl Iterator<Item = usize> + '_ {
+        (self.y0..self.y1).flat_map(move |y| (self.x0..self.x1).map(move |x| y * film_width + x))
+    }
+}
+
+/// Sub-rectangle of the image to render.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CropWindow {
+    /// Pixel bounds, `x1` and `y1` exclusive.
+    Pixels {
+        x0: usize,
+        y0: usize,
+        x1: usize,
+        y1: usize,
+    },
+    /// Bounds as fractions in [0,1] of the image width and height.
+    Normalized { x0: f64, y0: f64, x1: f64, y1: f64 },
+}
+
+impl CropWindow {
+    /// Pixel bounds of the window in a width x height image, clamped to the image.
+    pub fn bounds(&self, width: usize, height: usize) -> Tile {
+        let (x0, y0, x1, y1) = match *self {
+            CropWindow::Pixels { x0, y0, x1, y1 } => (x0, y0, x1, y1),
+            CropWindow::Normalized { x0, y0, x1, y1 } => {
+                let to_pixels =
+                    |t: f64, size: usize| (t.clamp(0.0, 1.0) * size as f64).ceil() as usize;
+                (
+                    to_pixels(x0, width),
+                    to_pixels(y0, height),
+                    to_pixels(x1, width),
+                    to_pixels(y1, height),
+                )
+            }
+        };
+        let (x1, y1) = (x1.min(width), y1.min(height));
+        Tile {
+            x0: x0.min(x1),
+            y0: y0.min(y1),
+            x1,
+            y1,
+        }
+    }
+}
+
+/// Order in which the tiles of a pass are handed out to the worker threads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TileOrder {
+    #[default]
+    Scanline, // Row by row from the top left corner
+    Spiral,  // Outwards from the center of the image
+    Hilbert, // Along a Hilbert curve, keeping consecutive tiles close together
+}
+
+/// Progress report sent after every finished tile.
+#[derive(Debug, Clone, Copy)]
+pub struct TileProgress {
+    pub tile: Tile,
+    pub pass: usize,        // Index of the progressive pass the tile belongs to
+    pub tiles_done: usize,  // Tiles finished so far in this pass
+    pub tiles_total: usize, // Tiles in this pass
+}
+
+pub type ProgressCallback = Arc<dyn Fn(&TileProgress) + Send + Sync>;
+
+/// Shared flag used to abort a render from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Splits a width x height image into tiles of at most tile_size x tile_size pixels.
+pub fn generate_tiles(
+    width: usize,
+    height: usize,
+    tile_size: usize,
+    order: TileOrder,
+) -> Vec<Tile> {
+    let tile_size = tile_size.max(1);
+    let nx = width.div_ceil(tile_size);
+    let ny = height.div_ceil(tile_size);
+
+    let coords = match order {
+        TileOrder::Scanline => (0..ny)
+            .flat_map(|ty| (0..nx).map(move |tx| (tx, ty)))
+            .collect(),
+        TileOrder::Spiral => spiral_order(nx, ny),
+        TileOrder::Hilbert => {
+            let n = nx.max(ny).next_power_of_two();
+            let mut coords: Vec<_> = (0..ny)
+                .flat_map(|ty| (0..nx).map(move |tx| (tx, ty)))
+                .collect();
+            coords.sort_by_key(|&(tx, ty)| hilbert_index(n, tx, ty));
+            coords
+        }
+    };
+
+    coords
+        .into_iter()
+        .map(|(tx, ty)| Tile {
+            x0: tx * tile_size,
+            y0: ty * tile_size,
+            x1: ((tx + 1) * tile_size).min(width),
+            y1: ((ty + 1) * tile_size).min(height),
+        })
+        .collect()
+}
+
+fn spiral_order(nx: usize, ny: usize) -> Vec<(usize, usize)> {
+    let mut coords = Vec::with_capacity(nx * ny);
+    let (mut x, mut y) = ((nx / 2) as i64, (ny / 2) as i64);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut leg = 0;
+
+    while coords.len() < nx * ny {
+        // Every two legs of the spiral grow one tile longer.
+        let (dx, dy) = directions[leg % 4];
+        for _ in 0..leg / 2 + 1 {
+            if (0..nx as i64).contains(&x) && (0..ny as i64).contains(&y) {
+                coords.push((x as usize, y as usize));
+            }
+            x += dx;
+            y += dy;
+        }
+        leg += 1;
+    }
+    coords
+}
+
+/// Distance of x,y along the Hilbert curve filling an n x n grid, n being a power of two.
+fn hilbert_index(n: usize, mut x: usize, mut y: usize) -> usize {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = usize::from(x & s > 0);
+        let ry = usize::from(y & s > 0);
+        d += s * s * ((3 * rx) ^ ry);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
@@ -0,0 +1,148 @@
+use image::Rgb;
+use nalgebra::{Matrix3, Vector3};
+
+use crate::util::luminance;
+
+/// Curve compressing linear radiance into the displayable [0,1] range.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ToneMapOperator {
+    /// Hard clip at 1.0.
+    #[default]
+    Clamp,
+    /// L / (1 + L) applied to the luminance.
+    Reinhard,
+    /// Reinhard variant mapping the luminance `white` to 1.0.
+    ExtendedReinhard { white: f64 },
+    /// ACES filmic curve, Stephen Hill's fit of the RRT and ODT.
+    Aces,
+    /// John Hable's Uncharted 2 filmic curve.
+    Hable,
+    /// Troy Sobotka's AgX, minimal polynomial fit.
+    AgX,
+}
+
+/// Display transform applied to linear radiance before quantizing to 8 bits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    pub exposure: f64, // Exposure adjustment in stops (EV), applied before the operator
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            operator: ToneMapOperator::Clamp,
+            exposure: 0.0,
+        }
+    }
+}
+
+/// sRGB opto-electronic transfer function (IEC 61966-2-1).
+pub fn srgb_oetf(x: f64) -> f64 {
+    if x <= 0.0031308 {
+        12.92 * x.max(0.0)
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn scale_luminance(color: Vector3<f64>, mapped: impl Fn(f64) -> f64) -> Vector3<f64> {
+    let l = luminance(&color);
+    if l <= 0.0 {
+        return Vector3::zeros();
+    }
+    color * (mapped(l) / l)
+}
+
+fn aces(color: Vector3<f64>) -> Vector3<f64> {
+    // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+    #[rustfmt::skip]
+    let input = Matrix3::new(
+        0.59719, 0.35458, 0.04823,
+        0.07600, 0.90834, 0.01566,
+        0.02840, 0.13383, 0.83777,
+    );
+    // ODT_SAT => XYZ => D60_2_D65 => sRGB
+    #[rustfmt::skip]
+    let output = Matrix3::new(
+        1.60475, -0.53108, -0.07367,
+        -0.10208, 1.10813, -0.00605,
+        -0.00327, -0.07276, 1.07602,
+    );
+    let v = input * color;
+    let rrt_odt =
+        v.map(|x| (x * (x + 0.0245786) - 0.000090537) / (x * (0.983729 * x + 0.432951) + 0.238081));
+    output * rrt_odt
+}
+
+fn hable_partial(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+fn hable(color: Vector3<f64>) -> Vector3<f64> {
+    let exposure_bias = 2.0;
+    let white = 11.2;
+    color.map(|c| hable_partial(c * exposure_bias) / hable_partial(white))
+}
+
+fn agx(color: Vector3<f64>) -> Vector3<f64> {
+    #[rustfmt::skip]
+    let inset = Matrix3::new(
+        0.842479062253094, 0.0784335999999992, 0.0792237451477643,
+        0.0423282422610123, 0.878468636469772, 0.0791661274605434,
+        0.0423756549057051, 0.0784336, 0.879142973793104,
+    );
+    #[rustfmt::skip]
+    let outset = Matrix3::new(
+        1.19687900512017, -0.0980208811401368, -0.0990297440797205,
+        -0.0528968517574562, 1.15190312990417, -0.0989611768448433,
+        -0.0529716355144438, -0.0980434501171241, 1.15107367264116,
+    );
+    let (min_ev, max_ev) = (-12.47393, 4.026069);
+
+    let v = (inset * color).map(|x| {
+        let x = (x.max(1e-10).log2().clamp(min_ev, max_ev) - min_ev) / (max_ev - min_ev);
+        // Sixth order polynomial approximation of the AgX sigmoid.
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    });
+    // The sigmoid outputs display encoded values, decode them back to linear.
+    (outset * v).map(|x| x.max(0.0).powf(2.2))
+}
+
+impl ToneMapOperator {
+    /// Maps linear radiance to linear display values in [0,1].
+    pub fn apply(&self, color: Vector3<f64>) -> Vector3<f64> {
+        let mapped = match *self {
+            ToneMapOperator::Clamp => color,
+            ToneMapOperator::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneMapOperator::ExtendedReinhard { white } => {
+                scale_luminance(color, |l| l * (1.0 + l / (white * white)) / (1.0 + l))
+            }
+            ToneMapOperator::Aces => aces(color),
+            ToneMapOperator::Hable => hable(color),
+            ToneMapOperator::AgX => agx(color),
+        };
+        mapped.map(|c| c.clamp(0.0, 1.0))
+    }
+}
+
+impl ToneMapping {
+    /// Applies exposure, the operator and the sRGB transfer function.
+    pub fn apply(&self, color: Vector3<f64>) -> Vector3<f64> {
+        let exposed = color * self.exposure.exp2();
+        self.operator.apply(exposed).map(srgb_oetf)
+    }
+
+    pub fn to_rgb(&self, color: Vector3<f64>) -> Rgb<u8> {
+        let display = self.apply(color);
+        Rgb([
+            (256.0 * display.x.clamp(0.0, 0.999)) as u8,
+            (256.0 * display.y.clamp(0.0, 0.999)) as u8,
+            (256.0 * display.z.clamp(0.0, 0.999)) as u8,
+        ])
+    }
+}
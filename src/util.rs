@@ -1,8 +1,10 @@
 use core::f64;
 use std::f64::consts::PI;
 
-use nalgebra::Vector3;
-use rand::{distributions::Uniform, thread_rng, Rng};
+use std::cell::RefCell;
+
+use nalgebra::{Vector2, Vector3};
+use crate::rng::Rng;
 #[derive(Debug, Clone)]
 pub struct Interval {
     pub min: f64,
@@ -60,15 +62,29 @@ impl Default for Interval {
     }
 }
 
-pub fn random_f64() -> f64 {
-    let mut rng = thread_rng();
+thread_local! {
+    // Every thread starts from the same fixed seed, so scenes built from random
+    // values come out identical between runs unless `seed_thread_rng` is called.
+    static RNG: RefCell<Rng> = RefCell::new(Rng::new(0, 0));
+}
 
-    rng.sample(Uniform::new(0.0, 1.0))
+/// Restarts the random stream of the current thread, the camera does this for
+/// every sample so renders are reproducible.
+pub fn seed_thread_rng(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = Rng::new(seed, 0));
 }
-pub fn random_range_f64(min: f64, max: f64) -> f64 {
-    let mut rng = thread_rng();
 
-    rng.sample(Uniform::new(min, max))
+pub fn random_f64() -> f64 {
+    RNG.with(|rng| rng.borrow_mut().next_f64())
+}
+pub fn random_u64() -> u64 {
+    RNG.with(|rng| {
+        let mut rng = rng.borrow_mut();
+        ((rng.next_u32() as u64) << 32) | rng.next_u32() as u64
+    })
+}
+pub fn random_range_f64(min: f64, max: f64) -> f64 {
+    min + (max - min) * random_f64()
 }
 
 /// Returns the vector to a random point in the [-.5,-.5]-[+.5,+.5] unit square.
@@ -133,19 +149,25 @@ pub fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
     r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
 }
 
-pub fn random_int(min: i32, max: i32) -> i32 {
-    let mut rng = thread_rng();
+/// Relative luminance of a linear Rec.709 color.
+pub fn luminance(color: &Vector3<f64>) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
 
-    rng.sample(Uniform::new(min, max))
+/// Returns a random integer in [min,max).
+pub fn random_int(min: i32, max: i32) -> i32 {
+    let value = min + (random_f64() * (max - min) as f64) as i32;
+    value.min(max - 1)
 }
 
 
 
 
 
-pub fn random_cosine_direction() -> Vector3<f64> {
-    let r1 = random_f64();
-    let r2 = random_f64();
+/// Cosine weighted direction around +z for the uniform sample `u`.
+pub fn cosine_direction(u: &Vector2<f64>) -> Vector3<f64> {
+    let r1 = u.x;
+    let r2 = u.y;
   
     let phi = 2.0 * PI * r1;
     let x = phi.cos() * r2.sqrt();
@@ -154,4 +176,18 @@ pub fn random_cosine_direction() -> Vector3<f64> {
   
     Vector3::new(x, y, z)
   }
-  
\ No newline at end of file
+
+/// Maps the uniform sample `u` to a point in the unit disk, preserving its
+/// stratification (Shirley and Chiu, concentric mapping).
+pub fn concentric_disk(u: &Vector2<f64>) -> Vector2<f64> {
+    let offset = 2.0 * u - Vector2::new(1.0, 1.0);
+    if offset.x == 0.0 && offset.y == 0.0 {
+        return Vector2::zeros();
+    }
+    let (r, theta) = if offset.x.abs() > offset.y.abs() {
+        (offset.x, PI / 4.0 * (offset.y / offset.x))
+    } else {
+        (offset.y, PI / 2.0 - PI / 4.0 * (offset.x / offset.y))
+    };
+    r * Vector2::new(theta.cos(), theta.sin())
+}
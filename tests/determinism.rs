@@ -0,0 +1,102 @@
+use nalgebra::Vector3;
+use pbrt_rs::{
+    camera::Camera,
+    film::Film,
+    hit::{quad::Quad, sphere::Sphere, Hittable},
+    integrator::Integrator,
+    material::{Dielectric, DiffuseLight, Lambertian, Material},
+    sampler::Sampler,
+    scene::Scene,
+};
+
+fn scene() -> (Hittable, Hittable) {
+    let white = Material::Diffuse(Lambertian::new_with_color(Vector3::new(0.73, 0.73, 0.73)));
+    let light =
+        Material::DiffuseLight(DiffuseLight::new_with_color(Vector3::new(15.0, 15.0, 15.0)));
+    let light_quad = Hittable::Quad(Quad::new(
+        Vector3::new(-1.0, 2.0, -1.0),
+        Vector3::new(2.0, 0.0, 0.0),
+        Vector3::new(0.0, 0.0, 2.0),
+        light,
+    ));
+
+    let mut world = Scene::default();
+    world.add(Hittable::Quad(Quad::new(
+        Vector3::new(-5.0, -1.0, -5.0),
+        Vector3::new(10.0, 0.0, 0.0),
+        Vector3::new(0.0, 0.0, 10.0),
+        white.clone(),
+    )));
+    world.add(Hittable::Sphere(Sphere::new(
+        Vector3::new(-0.6, 0.0, 0.0),
+        0.5,
+        white,
+    )));
+    world.add(Hittable::Sphere(Sphere::new(
+        Vector3::new(0.6, 0.0, 0.0),
+        0.5,
+        Material::Dielectric(Dielectric::new(1.5)),
+    )));
+    world.add(light_quad.clone());
+
+    let mut lights = Scene::default();
+    lights.add(light_quad);
+    (Hittable::PrefabScene(world), Hittable::PrefabScene(lights))
+}
+
+fn render(integrator: Integrator, sampler: Sampler, threads: usize) -> Film {
+    let mut camera = Camera::default();
+    camera.image_width = 24;
+    camera.samples_per_pixel = 4;
+    camera.integrator = integrator;
+    camera.sampler = sampler;
+    camera.lookfrom = Vector3::new(0.0, 0.5, -4.0);
+    camera.seed = 7;
+    let (world, lights) = scene();
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .unwrap();
+    pool.install(|| camera.render(world, lights))
+}
+
+fn assert_bit_identical(a: &Film, b: &Film) {
+    let bits = |film: &Film| -> Vec<u64> {
+        film.color
+            .iter()
+            .chain(&film.light)
+            .flat_map(|c| c.iter().map(|v| v.to_bits()).collect::<Vec<_>>())
+            .chain(film.weight.iter().map(|w| w.to_bits()))
+            .chain([film.light_paths.to_bits()])
+            .collect()
+    };
+    assert_eq!((a.width, a.height), (b.width, b.height));
+    assert_eq!(a.samples, b.samples);
+    assert!(bits(a) == bits(b), "films differ");
+}
+
+#[test]
+fn same_seed_renders_identically_on_any_thread_count() {
+    for integrator in [Integrator::Path, Integrator::Bidirectional] {
+        let single = render(integrator, Sampler::default(), 1);
+        let multi = render(integrator, Sampler::default(), 4);
+        assert!(single.color.iter().any(|c| *c != Vector3::zeros()));
+        assert_bit_identical(&single, &multi);
+    }
+}
+
+#[test]
+fn every_sampler_renders_deep_paths() {
+    for sampler in [
+        Sampler::Independent,
+        Sampler::Stratified,
+        Sampler::Halton,
+        Sampler::Sobol,
+        Sampler::BlueNoise,
+    ] {
+        let film = render(Integrator::Path, sampler, 1);
+        let finite = (0..film.height)
+            .all(|y| (0..film.width).all(|x| film.pixel(x, y).iter().all(|c| c.is_finite())));
+        assert!(finite, "{:?} rendered non-finite pixels", sampler);
+    }
+}